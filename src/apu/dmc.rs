@@ -0,0 +1,243 @@
+//! The delta modulation channel, which plays 1 bit delta encoded
+//! samples read from CPU memory: http://wiki.nesdev.com/w/index.php/APU_DMC
+
+use region::Region;
+
+/// Timer periods selected by 0x4010, in CPU cycles.
+const NTSC_RATES: [u16; 16] = [
+    428, 380, 340, 320, 286, 254, 226, 214, 190, 160, 142, 128, 106, 84, 72, 54,
+];
+const PAL_RATES: [u16; 16] = [
+    398, 354, 316, 298, 276, 236, 210, 198, 176, 148, 132, 118, 98, 78, 66, 50,
+];
+
+pub struct Dmc {
+    rates: &'static [u16; 16],
+    irq_enabled: bool,
+    irq: bool,
+    looping: bool,
+    rate: u16,
+    timer: u16,
+    level: u8,
+
+    // The memory reader.
+    sample_address: u16,
+    sample_length: u16,
+    address: u16,
+    bytes_remaining: u16,
+    buffer: Option<u8>,
+
+    // The output unit.
+    shift: u8,
+    bits_remaining: u8,
+    silence: bool,
+}
+
+impl Dmc {
+    pub fn new(region: Region) -> Dmc {
+        let rates = match region {
+            Region::NTSC => &NTSC_RATES,
+            Region::PAL => &PAL_RATES,
+        };
+        Dmc {
+            rates,
+            irq_enabled: false,
+            irq: false,
+            looping: false,
+            rate: rates[0],
+            timer: 0,
+            level: 0,
+            sample_address: 0xc000,
+            sample_length: 1,
+            address: 0xc000,
+            bytes_remaining: 0,
+            buffer: None,
+            shift: 0,
+            bits_remaining: 8,
+            silence: true,
+        }
+    }
+
+    /// Write to one of the channel's registers at 0x4010-0x4013.
+    pub fn write(&mut self, register: u16, x: u8) {
+        match register {
+            // IL-- RRRR: IRQ enable, loop and rate.
+            0 => {
+                self.irq_enabled = x & 0x80 != 0;
+                if !self.irq_enabled {
+                    self.irq = false;
+                }
+                self.looping = x & 0x40 != 0;
+                self.rate = self.rates[(x & 0x0f) as usize];
+            },
+            // -DDD DDDD: load the output level directly.
+            1 => self.level = x & 0x7f,
+            // Samples start at 0xc000 + A * 64 and are L * 16 + 1 bytes long.
+            2 => self.sample_address = 0xc000 | (x as u16) << 6,
+            3 => self.sample_length = (x as u16) << 4 | 1,
+            _ => unreachable!(),
+        }
+    }
+
+    /// Set through 0x4015, which also acknowledges the interrupt.
+    /// Enabling the channel starts the sample unless some of it is
+    /// still to be read, disabling it stops reading.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.irq = false;
+        if !enabled {
+            self.bytes_remaining = 0;
+        } else if self.bytes_remaining == 0 {
+            self.restart();
+        }
+    }
+
+    fn restart(&mut self) {
+        self.address = self.sample_address;
+        self.bytes_remaining = self.sample_length;
+    }
+
+    /// Whether there are still bytes of the sample to read.
+    pub fn active(&self) -> bool {
+        self.bytes_remaining > 0
+    }
+
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// The address of the next sample byte, if the sample buffer is
+    /// empty and there is one to read, which needs a DMA to fetch.
+    pub fn dma_address(&self) -> Option<u16> {
+        if self.buffer.is_none() && self.bytes_remaining > 0 {
+            Some(self.address)
+        } else {
+            None
+        }
+    }
+
+    /// Fill the sample buffer with the byte read by the DMA.
+    pub fn fill(&mut self, x: u8) {
+        self.buffer = Some(x);
+        // The address wraps around to 0x8000.
+        self.address = self.address.wrapping_add(1) | 0x8000;
+        self.bytes_remaining -= 1;
+        if self.bytes_remaining == 0 {
+            if self.looping {
+                self.restart();
+            } else if self.irq_enabled {
+                self.irq = true;
+            }
+        }
+    }
+
+    /// Clocked every CPU cycle, as the rates are in CPU cycles.
+    pub fn clock_timer(&mut self) {
+        if self.timer > 0 {
+            self.timer -= 1;
+            return;
+        }
+        self.timer = self.rate - 1;
+
+        // Each bit moves the level up or down by 2, unless it would
+        // leave the range 0-127.
+        if !self.silence {
+            if self.shift & 1 != 0 {
+                if self.level <= 125 {
+                    self.level += 2;
+                }
+            } else if self.level >= 2 {
+                self.level -= 2;
+            }
+        }
+        self.shift >>= 1;
+
+        self.bits_remaining -= 1;
+        if self.bits_remaining == 0 {
+            self.bits_remaining = 8;
+            match self.buffer.take() {
+                Some(x) => {
+                    self.shift = x;
+                    self.silence = false;
+                },
+                None => self.silence = true,
+            }
+        }
+    }
+
+    /// The current output level, from 0 to 127.
+    pub fn output(&self) -> u8 {
+        self.level
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Clock through a whole output cycle of 8 bits.
+    fn play_byte(dmc: &mut Dmc) {
+        for _ in 0..8 * dmc.rate {
+            dmc.clock_timer();
+        }
+    }
+
+    #[test]
+    fn test_reads_sample_and_wraps() {
+        let mut dmc = Dmc::new(Region::NTSC);
+        dmc.write(2, 0xff);
+        // 65 bytes, the last at 0x8000.
+        dmc.write(3, 0x04);
+        dmc.set_enabled(true);
+        for i in 0..64 {
+            assert_eq!(dmc.dma_address(), Some(0xffc0 + i));
+            dmc.fill(0);
+            assert_eq!(dmc.dma_address(), None);
+            play_byte(&mut dmc);
+        }
+        assert_eq!(dmc.dma_address(), Some(0x8000));
+        dmc.fill(0);
+        assert!(!dmc.active());
+    }
+
+    #[test]
+    fn test_output_unit() {
+        let mut dmc = Dmc::new(Region::NTSC);
+        dmc.write(0, 0x0f);
+        dmc.write(1, 0x40);
+        dmc.set_enabled(true);
+        dmc.fill(0b0000_0111);
+        // The buffer is emptied into the shift register at
+        // the end of the current (silent) output cycle.
+        play_byte(&mut dmc);
+        assert_eq!(dmc.output(), 0x40);
+        play_byte(&mut dmc);
+        assert_eq!(dmc.output(), 0x40 + 3 * 2 - 5 * 2);
+        // Clamped at the top of the range.
+        dmc.write(1, 0x7e);
+        dmc.set_enabled(true);
+        dmc.fill(0xff);
+        play_byte(&mut dmc);
+        play_byte(&mut dmc);
+        assert_eq!(dmc.output(), 0x7e);
+    }
+
+    #[test]
+    fn test_loop_and_irq() {
+        let mut dmc = Dmc::new(Region::NTSC);
+        dmc.write(0, 0x40);
+        dmc.set_enabled(true);
+        dmc.fill(0);
+        assert!(dmc.active());
+        assert_eq!(dmc.dma_address(), None);
+        assert!(!dmc.irq());
+
+        let mut dmc = Dmc::new(Region::NTSC);
+        dmc.write(0, 0x80);
+        dmc.set_enabled(true);
+        dmc.fill(0);
+        assert!(!dmc.active());
+        assert!(dmc.irq());
+        dmc.set_enabled(false);
+        assert!(!dmc.irq());
+    }
+}
@@ -0,0 +1,86 @@
+//! The volume envelope of the pulse and noise channels:
+//! http://wiki.nesdev.com/w/index.php/APU_Envelope
+
+#[derive(Default)]
+pub struct Envelope {
+    start: bool,
+    looping: bool,
+    constant: bool,
+    // The constant volume, or the period of the divider.
+    volume: u8,
+    divider: u8,
+    decay: u8,
+}
+
+impl Envelope {
+    /// Set from the low 6 bits of the channel's first register,
+    /// --LC VVVV: loop, constant volume and volume/period.
+    pub fn write(&mut self, x: u8) {
+        self.looping = x & 0x20 != 0;
+        self.constant = x & 0x10 != 0;
+        self.volume = x & 0x0f;
+    }
+
+    /// Start decaying from 15 at the next quarter frame.
+    pub fn restart(&mut self) {
+        self.start = true;
+    }
+
+    /// Clocked by the frame counter every quarter frame.
+    pub fn clock(&mut self) {
+        if self.start {
+            self.start = false;
+            self.decay = 15;
+            self.divider = self.volume;
+        } else if self.divider == 0 {
+            self.divider = self.volume;
+            if self.decay > 0 {
+                self.decay -= 1;
+            } else if self.looping {
+                self.decay = 15;
+            }
+        } else {
+            self.divider -= 1;
+        }
+    }
+
+    pub fn volume(&self) -> u8 {
+        if self.constant { self.volume } else { self.decay }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_decay() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x01);
+        envelope.restart();
+        envelope.clock();
+        assert_eq!(envelope.volume(), 15);
+        // The divider reloads with a period of 2 clocks.
+        for _ in 0..2 * 15 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+        for _ in 0..4 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 0);
+    }
+
+    #[test]
+    fn test_loop_and_constant_volume() {
+        let mut envelope = Envelope::default();
+        envelope.write(0x20);
+        envelope.restart();
+        for _ in 0..17 {
+            envelope.clock();
+        }
+        assert_eq!(envelope.volume(), 15);
+        envelope.write(0x17);
+        assert_eq!(envelope.volume(), 7);
+    }
+}
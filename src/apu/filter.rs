@@ -0,0 +1,85 @@
+//! The first order filters between the mixer and the output:
+//! http://wiki.nesdev.com/w/index.php/APU_Mixer
+//!
+//! The NES has two high-pass filters at 90Hz and 440Hz and a
+//! low-pass filter at 14kHz.
+
+use std::f32::consts::PI;
+
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Kind {
+    HighPass,
+    LowPass,
+}
+
+pub struct Filter {
+    kind: Kind,
+    alpha: f32,
+    last_input: f32,
+    last_output: f32,
+}
+
+impl Filter {
+    fn new(kind: Kind, cutoff: f32, sample_rate: f32) -> Filter {
+        let rc = 1.0 / (2.0 * PI * cutoff);
+        let dt = 1.0 / sample_rate;
+        Filter {
+            kind,
+            alpha: match kind {
+                Kind::HighPass => rc / (rc + dt),
+                Kind::LowPass => dt / (rc + dt),
+            },
+            last_input: 0.0,
+            last_output: 0.0,
+        }
+    }
+
+    pub fn high_pass(cutoff: f32, sample_rate: f32) -> Filter {
+        Filter::new(Kind::HighPass, cutoff, sample_rate)
+    }
+
+    pub fn low_pass(cutoff: f32, sample_rate: f32) -> Filter {
+        Filter::new(Kind::LowPass, cutoff, sample_rate)
+    }
+
+    pub fn process(&mut self, x: f32) -> f32 {
+        let y = match self.kind {
+            Kind::HighPass => self.alpha * (self.last_output + x - self.last_input),
+            Kind::LowPass => self.last_output + self.alpha * (x - self.last_output),
+        };
+        self.last_input = x;
+        self.last_output = y;
+        y
+    }
+}
+
+/// The NES's filters, in the order the signal passes through them.
+pub fn nes_filters(sample_rate: f32) -> Vec<Filter> {
+    vec![
+        Filter::high_pass(90.0, sample_rate),
+        Filter::high_pass(440.0, sample_rate),
+        Filter::low_pass(14000.0, sample_rate),
+    ]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_high_pass_removes_dc() {
+        let mut filter = Filter::high_pass(90.0, 44100.0);
+        let first = filter.process(1.0);
+        assert!(first > 0.95);
+        let last = (0..44100).map(|_| filter.process(1.0)).last().unwrap();
+        assert!(last.abs() < 0.001);
+    }
+
+    #[test]
+    fn test_low_pass_passes_dc() {
+        let mut filter = Filter::low_pass(14000.0, 44100.0);
+        assert!(filter.process(1.0) < 1.0);
+        let last = (0..100).map(|_| filter.process(1.0)).last().unwrap();
+        assert!((last - 1.0).abs() < 0.001);
+    }
+}
@@ -0,0 +1,162 @@
+//! The frame counter, which clocks the channels' envelopes, length
+//! counters and sweeps and can raise an IRQ:
+//! http://wiki.nesdev.com/w/index.php/APU_Frame_Counter
+
+use region::Region;
+
+/// CPU cycles into the sequence of each step, the fourth
+/// ending the 4 step sequence and the fifth the 5 step one.
+const NTSC_STEPS: [u32; 5] = [7457, 14913, 22371, 29829, 37281];
+const PAL_STEPS: [u32; 5] = [8313, 16627, 24939, 33253, 41565];
+
+/// What the frame counter clocks on a cycle.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Clock {
+    None,
+    /// Envelopes and the triangle's linear counter.
+    QuarterFrame,
+    /// Everything clocked each quarter frame,
+    /// plus the length counters and sweeps.
+    HalfFrame,
+}
+
+pub struct FrameCounter {
+    steps: &'static [u32; 5],
+    five_step: bool,
+    irq_inhibit: bool,
+    irq: bool,
+    // CPU cycles into the sequence.
+    cycle: u32,
+    // CPU cycles until a write to 0x4017 restarts the sequence.
+    restart_delay: Option<u8>,
+}
+
+impl FrameCounter {
+    pub fn new(region: Region) -> FrameCounter {
+        FrameCounter {
+            steps: match region {
+                Region::NTSC => &NTSC_STEPS,
+                Region::PAL => &PAL_STEPS,
+            },
+            five_step: false,
+            irq_inhibit: false,
+            irq: false,
+            cycle: 0,
+            restart_delay: None,
+        }
+    }
+
+    /// Write to 0x4017, MI-- ----: mode and IRQ inhibit. The sequence
+    /// restarts 3 CPU cycles later if written during an APU cycle,
+    /// or 4 if written between them.
+    pub fn write(&mut self, x: u8, apu_cycle: bool) {
+        self.five_step = x & 0x80 != 0;
+        self.irq_inhibit = x & 0x40 != 0;
+        if self.irq_inhibit {
+            self.irq = false;
+        }
+        self.restart_delay = Some(if apu_cycle { 3 } else { 4 });
+    }
+
+    /// The frame interrupt flag, which holds the IRQ line low while set.
+    pub fn irq(&self) -> bool {
+        self.irq
+    }
+
+    /// Reading 0x4015 acknowledges the interrupt.
+    pub fn clear_irq(&mut self) {
+        self.irq = false;
+    }
+
+    /// Run the frame counter for one CPU cycle.
+    pub fn step(&mut self) -> Clock {
+        if let Some(delay) = self.restart_delay {
+            if delay > 1 {
+                self.restart_delay = Some(delay - 1);
+            } else {
+                self.restart_delay = None;
+                self.cycle = 0;
+                // The 5 step sequence clocks everything straight away.
+                return if self.five_step { Clock::HalfFrame } else { Clock::None };
+            }
+        }
+
+        self.cycle += 1;
+        let steps = self.steps;
+        let last = if self.five_step { steps[4] } else { steps[3] };
+
+        // The 4 step sequence raises the IRQ on the
+        // cycle either side of its last step too.
+        if !self.five_step && !self.irq_inhibit && (last - 1 ..= last + 1).contains(&self.cycle) {
+            self.irq = true;
+        }
+
+        let clock = if self.cycle == steps[0] || self.cycle == steps[2] {
+            Clock::QuarterFrame
+        } else if self.cycle == steps[1] || self.cycle == last {
+            Clock::HalfFrame
+        } else {
+            Clock::None
+        };
+        if self.cycle == last + 1 {
+            self.cycle = 0;
+        }
+        clock
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The cycles on which the frame counter clocks
+    /// something, over `cycles` cycles.
+    fn clocks(frame_counter: &mut FrameCounter, cycles: u32) -> Vec<(u32, Clock)> {
+        (1..=cycles)
+            .map(|cycle| (cycle, frame_counter.step()))
+            .filter(|&(_, clock)| clock != Clock::None)
+            .collect()
+    }
+
+    #[test]
+    fn test_four_step_sequence() {
+        let mut frame_counter = FrameCounter::new(Region::NTSC);
+        assert_eq!(clocks(&mut frame_counter, 29830 + 7457), vec![
+            (7457, Clock::QuarterFrame),
+            (14913, Clock::HalfFrame),
+            (22371, Clock::QuarterFrame),
+            (29829, Clock::HalfFrame),
+            (29830 + 7457, Clock::QuarterFrame),
+        ]);
+    }
+
+    #[test]
+    fn test_four_step_irq() {
+        let mut frame_counter = FrameCounter::new(Region::NTSC);
+        clocks(&mut frame_counter, 29827);
+        assert!(!frame_counter.irq());
+        frame_counter.step();
+        assert!(frame_counter.irq());
+        frame_counter.clear_irq();
+        frame_counter.step();
+        frame_counter.step();
+        assert!(frame_counter.irq());
+        frame_counter.write(0x40, false);
+        assert!(!frame_counter.irq());
+    }
+
+    #[test]
+    fn test_five_step_sequence() {
+        let mut frame_counter = FrameCounter::new(Region::PAL);
+        frame_counter.write(0x80, true);
+        let sequence = clocks(&mut frame_counter, 3 + 41566);
+        assert_eq!(sequence, vec![
+            (3, Clock::HalfFrame),
+            (3 + 8313, Clock::QuarterFrame),
+            (3 + 16627, Clock::HalfFrame),
+            (3 + 24939, Clock::QuarterFrame),
+            (3 + 41565, Clock::HalfFrame),
+        ]);
+        assert!(!frame_counter.irq());
+    }
+}
@@ -1,3 +1,284 @@
+use region::Region;
+use super::dmc::Dmc;
+use super::filter::{self, Filter};
+use super::frame_counter::{Clock, FrameCounter};
+use super::mixer::Mixer;
+use super::noise::Noise;
+use super::pulse::{Channel, Pulse};
+use super::resampler::Resampler;
+use super::triangle::Triangle;
+
+/// The rate the APU is clocked at, the CPU's clock rate.
+const NTSC_CLOCK_RATE: f64 = 1_789_773.0;
+const PAL_CLOCK_RATE: f64 = 1_662_607.0;
+
+pub const DEFAULT_SAMPLE_RATE: u32 = 44100;
+
+/// The APU's registers at 0x4000-0x4017 and the channels behind them.
 pub struct ApuInterface {
-    
-}
\ No newline at end of file
+    pulses: [Pulse; 2],
+    triangle: Triangle,
+    noise: Noise,
+    dmc: Dmc,
+    frame_counter: FrameCounter,
+    mixer: Mixer,
+    clock_rate: f64,
+    resampler: Resampler,
+    filters: Vec<Filter>,
+    // The pulse channels count APU cycles, every other CPU cycle.
+    odd_cycle: bool,
+}
+
+impl ApuInterface {
+    pub fn new(region: Region) -> Self {
+        let clock_rate = match region {
+            Region::NTSC => NTSC_CLOCK_RATE,
+            Region::PAL => PAL_CLOCK_RATE,
+        };
+        ApuInterface {
+            pulses: [Pulse::new(Channel::One), Pulse::new(Channel::Two)],
+            triangle: Triangle::new(),
+            noise: Noise::new(region),
+            dmc: Dmc::new(region),
+            frame_counter: FrameCounter::new(region),
+            mixer: Mixer::new(),
+            clock_rate,
+            resampler: Resampler::new(clock_rate, DEFAULT_SAMPLE_RATE),
+            filters: filter::nes_filters(DEFAULT_SAMPLE_RATE as f32),
+            odd_cycle: false,
+        }
+    }
+
+    /// Read 0x4015, IF-D NT21: the DMC and frame interrupt flags, and
+    /// whether each channel is still playing. Bit 5 is left as 0 as
+    /// it is not driven. Acknowledges the frame interrupt.
+    pub fn read_status(&mut self) -> u8 {
+        let status = (self.dmc.irq() as u8) << 7
+            | (self.frame_counter.irq() as u8) << 6
+            | (self.dmc.active() as u8) << 4
+            | (self.noise.active() as u8) << 3
+            | (self.triangle.active() as u8) << 2
+            | (self.pulses[1].active() as u8) << 1
+            | (self.pulses[0].active() as u8);
+        self.frame_counter.clear_irq();
+        status
+    }
+
+    pub fn write_register(&mut self, addr: u16, x: u8) {
+        match addr {
+            0x4000 ..= 0x4003 => self.pulses[0].write(addr - 0x4000, x),
+            0x4004 ..= 0x4007 => self.pulses[1].write(addr - 0x4004, x),
+            0x4008 ..= 0x400b => self.triangle.write(addr - 0x4008, x),
+            0x400c ..= 0x400f => self.noise.write(addr - 0x400c, x),
+            0x4010 ..= 0x4013 => self.dmc.write(addr - 0x4010, x),
+            // ---D NT21: enable each channel.
+            0x4015 => {
+                self.pulses[0].set_enabled(x & 0b0001 != 0);
+                self.pulses[1].set_enabled(x & 0b0010 != 0);
+                self.triangle.set_enabled(x & 0b0100 != 0);
+                self.noise.set_enabled(x & 0b1000 != 0);
+                self.dmc.set_enabled(x & 0b1_0000 != 0);
+            },
+            0x4017 => self.frame_counter.write(x, self.odd_cycle),
+            _ => panic!("Invalid APU register: {:#x}", addr),
+        }
+    }
+
+    /// The pulse channel behind 0x4000 (0) or 0x4004 (1).
+    pub fn pulse(&self, index: usize) -> &Pulse {
+        &self.pulses[index]
+    }
+
+    pub fn triangle(&self) -> &Triangle {
+        &self.triangle
+    }
+
+    pub fn noise(&self) -> &Noise {
+        &self.noise
+    }
+
+    pub fn dmc(&self) -> &Dmc {
+        &self.dmc
+    }
+
+    /// The address the DMC needs read into its sample buffer, if any.
+    pub fn dmc_dma_address(&self) -> Option<u16> {
+        self.dmc.dma_address()
+    }
+
+    /// Fill the DMC's sample buffer with the byte it asked for.
+    pub fn dmc_fill(&mut self, x: u8) {
+        self.dmc.fill(x);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.resampler.sample_rate()
+    }
+
+    /// Change the rate audio is output at, dropping any
+    /// samples which have not been drained yet.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.resampler = Resampler::new(self.clock_rate, sample_rate);
+        self.filters = filter::nes_filters(sample_rate as f32);
+    }
+
+    /// Audio samples ready to be drained.
+    pub fn samples_available(&self) -> usize {
+        self.resampler.available()
+    }
+
+    /// Move the audio output so far onto the end of `out`, as
+    /// samples from -1 to 1 at the sample rate.
+    pub fn drain_audio(&mut self, out: &mut Vec<f32>) {
+        let filters = &mut self.filters;
+        self.resampler.read(out, |sample| {
+            filters.iter_mut().fold(sample, |sample, filter| filter.process(sample))
+        });
+    }
+
+    /// Level of the APU's IRQ output.
+    pub fn irq(&self) -> bool {
+        self.frame_counter.irq() || self.dmc.irq()
+    }
+
+    /// Run the APU for one CPU cycle.
+    pub fn step(&mut self) {
+        if self.odd_cycle {
+            for pulse in &mut self.pulses {
+                pulse.clock_timer();
+            }
+        }
+        self.triangle.clock_timer();
+        self.noise.clock_timer();
+        self.dmc.clock_timer();
+        self.odd_cycle = !self.odd_cycle;
+
+        match self.frame_counter.step() {
+            Clock::None => (),
+            Clock::QuarterFrame => self.clock_quarter_frame(),
+            Clock::HalfFrame => {
+                self.clock_quarter_frame();
+                for pulse in &mut self.pulses {
+                    pulse.clock_half_frame();
+                }
+                self.triangle.clock_half_frame();
+                self.noise.clock_half_frame();
+            },
+        }
+
+        let level = self.mixer.mix(self.pulses[0].output(),
+                                   self.pulses[1].output(),
+                                   self.triangle.output(),
+                                   self.noise.output(),
+                                   self.dmc.output());
+        self.resampler.set_level(level);
+        self.resampler.clock();
+        // Keep no more than a second of audio if nothing is draining it.
+        let sample_rate = self.sample_rate() as usize;
+        self.resampler.discard(sample_rate);
+    }
+
+    fn clock_quarter_frame(&mut self) {
+        for pulse in &mut self.pulses {
+            pulse.clock_quarter_frame();
+        }
+        self.triangle.clock_quarter_frame();
+        self.noise.clock_quarter_frame();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pulse_registers() {
+        let mut apu = ApuInterface::new(Region::NTSC);
+        apu.write_register(0x4015, 0b10);
+        for &addr in &[0x4000, 0x4004] {
+            apu.write_register(addr, 0xbf);
+            apu.write_register(addr + 2, 0x00);
+            apu.write_register(addr + 3, 0x01);
+        }
+        // Only pulse 2 is enabled to load its length counter.
+        assert!(!apu.pulse(0).active());
+        assert!(apu.pulse(1).active());
+        apu.write_register(0x4015, 0);
+        assert!(!apu.pulse(1).active());
+    }
+
+    #[test]
+    fn test_frame_counter_clocks_length() {
+        let mut apu = ApuInterface::new(Region::NTSC);
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0x10);
+        // A length of 2 half frames.
+        apu.write_register(0x4003, 0x18);
+        for _ in 0..14913 {
+            apu.step();
+        }
+        assert!(apu.pulse(0).active());
+        for _ in 14913..29829 {
+            apu.step();
+        }
+        assert!(!apu.pulse(0).active());
+    }
+
+    #[test]
+    fn test_status() {
+        let mut apu = ApuInterface::new(Region::NTSC);
+        apu.write_register(0x4015, 0b1111);
+        for &addr in &[0x4003, 0x4007, 0x400b, 0x400f] {
+            apu.write_register(addr, 0x08);
+        }
+        assert_eq!(apu.read_status(), 0b0000_1111);
+        apu.write_register(0x4015, 0b0101);
+        assert_eq!(apu.read_status(), 0b0000_0101);
+        // A one byte sample raising an IRQ once it has been read.
+        apu.write_register(0x4010, 0x80);
+        apu.write_register(0x4015, 0b1_0000);
+        assert_eq!(apu.read_status(), 0b0001_0000);
+        apu.dmc_fill(0);
+        assert_eq!(apu.read_status(), 0b1000_0000);
+        assert!(apu.irq());
+    }
+
+    #[test]
+    fn test_frame_interrupt() {
+        let mut apu = ApuInterface::new(Region::NTSC);
+        for _ in 0..29828 {
+            apu.step();
+        }
+        assert!(apu.irq());
+        assert_eq!(apu.read_status(), 0b0100_0000);
+        assert!(!apu.irq());
+        // Inhibited, or in the 5 step sequence, there is no interrupt.
+        apu.write_register(0x4017, 0x40);
+        for _ in 0..2 * 29830 {
+            apu.step();
+        }
+        assert!(!apu.irq());
+    }
+
+    #[test]
+    fn test_audio_output() {
+        let mut apu = ApuInterface::new(Region::NTSC);
+        apu.set_sample_rate(48000);
+        // Pulse 1 at constant volume 15 and about 440Hz.
+        apu.write_register(0x4015, 0b01);
+        apu.write_register(0x4000, 0xbf);
+        apu.write_register(0x4002, 0xfd);
+        apu.write_register(0x4003, 0x00);
+        for _ in 0..NTSC_CLOCK_RATE as usize / 10 {
+            apu.step();
+        }
+        let mut samples = Vec::new();
+        apu.drain_audio(&mut samples);
+        assert!((4790..=4800).contains(&samples.len()), "{}", samples.len());
+        assert_eq!(apu.samples_available(), 0);
+        assert!(samples.iter().all(|&sample| (-1.0..=1.0).contains(&sample)));
+        // The square wave swings either side of 0 once filtered.
+        let (min, max) = samples.iter().fold((0.0f32, 0.0f32), |(min, max), &s| (min.min(s), max.max(s)));
+        assert!(min < -0.05 && max > 0.05, "{} {}", min, max);
+    }
+}
@@ -0,0 +1,80 @@
+//! The length counter which silences a channel after a set time:
+//! http://wiki.nesdev.com/w/index.php/APU_Length_Counter
+
+/// Lengths loaded by the top 5 bits of a channel's last register.
+const LENGTHS: [u8; 32] = [
+    10, 254, 20, 2, 40, 4, 80, 6, 160, 8, 60, 10, 14, 12, 26, 14,
+    12, 16, 24, 18, 48, 20, 96, 22, 192, 24, 72, 26, 16, 28, 32, 30,
+];
+
+#[derive(Default)]
+pub struct LengthCounter {
+    enabled: bool,
+    halted: bool,
+    counter: u8,
+}
+
+impl LengthCounter {
+    /// Set through 0x4015. Disabling the channel clears the counter.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.counter = 0;
+        }
+    }
+
+    pub fn set_halted(&mut self, halted: bool) {
+        self.halted = halted;
+    }
+
+    /// Load the length from the top 5 bits of a write to
+    /// the channel's last register, if it is enabled.
+    pub fn load(&mut self, x: u8) {
+        if self.enabled {
+            self.counter = LENGTHS[(x >> 3) as usize];
+        }
+    }
+
+    /// Clocked by the frame counter every half frame.
+    pub fn clock(&mut self) {
+        if !self.halted && self.counter > 0 {
+            self.counter -= 1;
+        }
+    }
+
+    pub fn active(&self) -> bool {
+        self.counter > 0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_load_only_when_enabled() {
+        let mut length = LengthCounter::default();
+        length.load(0x08);
+        assert!(!length.active());
+        length.set_enabled(true);
+        length.load(0x18);
+        assert_eq!(length.counter, 2);
+        length.set_enabled(false);
+        assert!(!length.active());
+    }
+
+    #[test]
+    fn test_counts_down_unless_halted() {
+        let mut length = LengthCounter::default();
+        length.set_enabled(true);
+        length.load(0x18);
+        length.set_halted(true);
+        length.clock();
+        assert_eq!(length.counter, 2);
+        length.set_halted(false);
+        length.clock();
+        length.clock();
+        length.clock();
+        assert_eq!(length.counter, 0);
+    }
+}
@@ -0,0 +1,52 @@
+//! The non-linear DAC combining the channels' outputs:
+//! http://wiki.nesdev.com/w/index.php/APU_Mixer
+//!
+//! The pulse channels share one DAC and the triangle, noise and
+//! DMC another, each approximated with a lookup table.
+
+pub struct Mixer {
+    pulse: [f32; 31],
+    tnd: [f32; 203],
+}
+
+impl Default for Mixer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Mixer {
+    pub fn new() -> Mixer {
+        let mut pulse = [0.0; 31];
+        for (n, level) in pulse.iter_mut().enumerate().skip(1) {
+            *level = 95.52 / (8128.0 / n as f32 + 100.0);
+        }
+        let mut tnd = [0.0; 203];
+        for (n, level) in tnd.iter_mut().enumerate().skip(1) {
+            *level = 163.67 / (24329.0 / n as f32 + 100.0);
+        }
+        Mixer { pulse, tnd }
+    }
+
+    /// Mix the channels' output levels into an amplitude from 0 to 1.
+    pub fn mix(&self, pulse1: u8, pulse2: u8, triangle: u8, noise: u8, dmc: u8) -> f32 {
+        let pulse = self.pulse[(pulse1 + pulse2) as usize];
+        let tnd = self.tnd[3 * triangle as usize + 2 * noise as usize + dmc as usize];
+        pulse + tnd
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_levels() {
+        let mixer = Mixer::new();
+        assert_eq!(mixer.mix(0, 0, 0, 0, 0), 0.0);
+        let full = mixer.mix(15, 15, 15, 15, 127);
+        assert!((full - 1.0).abs() < 0.001, "{}", full);
+        // The pulses together are quieter than the sum of each.
+        assert!(mixer.mix(15, 15, 0, 0, 0) < 2.0 * mixer.mix(15, 0, 0, 0, 0));
+    }
+}
@@ -0,0 +1,17 @@
+mod dmc;
+mod envelope;
+mod filter;
+mod frame_counter;
+mod interface;
+mod length_counter;
+mod mixer;
+mod noise;
+mod pulse;
+mod resampler;
+mod triangle;
+
+pub use self::dmc::{Dmc};
+pub use self::interface::{ApuInterface, DEFAULT_SAMPLE_RATE};
+pub use self::noise::{Noise};
+pub use self::pulse::{Pulse};
+pub use self::triangle::{Triangle};
@@ -0,0 +1,152 @@
+//! The noise channel: http://wiki.nesdev.com/w/index.php/APU_Noise
+
+use region::Region;
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+/// Timer periods selected by 0x400e, in CPU cycles.
+const NTSC_PERIODS: [u16; 16] = [
+    4, 8, 16, 32, 64, 96, 128, 160, 202, 254, 380, 508, 762, 1016, 2034, 4068,
+];
+const PAL_PERIODS: [u16; 16] = [
+    4, 8, 14, 30, 60, 88, 118, 148, 188, 236, 354, 472, 708, 944, 1890, 3778,
+];
+
+pub struct Noise {
+    periods: &'static [u16; 16],
+    period: u16,
+    timer: u16,
+    // Feed back from bit 6 rather than bit 1, for a short,
+    // metallic sounding sequence.
+    short_mode: bool,
+    shift: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+}
+
+impl Noise {
+    pub fn new(region: Region) -> Noise {
+        let periods = match region {
+            Region::NTSC => &NTSC_PERIODS,
+            Region::PAL => &PAL_PERIODS,
+        };
+        Noise {
+            periods,
+            period: periods[0],
+            timer: 0,
+            short_mode: false,
+            shift: 1,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+        }
+    }
+
+    /// Write to one of the channel's registers at 0x400c-0x400f.
+    pub fn write(&mut self, register: u16, x: u8) {
+        match register {
+            // --LC VVVV: length halt/envelope loop, envelope.
+            0 => {
+                self.length.set_halted(x & 0x20 != 0);
+                self.envelope.write(x);
+            },
+            1 => (),
+            // M--- PPPP: mode and period.
+            2 => {
+                self.short_mode = x & 0x80 != 0;
+                self.period = self.periods[(x & 0x0f) as usize];
+            },
+            // LLLL L---: length, which also restarts the envelope.
+            3 => {
+                self.length.load(x);
+                self.envelope.restart();
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    /// Clocked every CPU cycle, as the periods are in CPU cycles.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period - 1;
+            let tap = if self.short_mode { 6 } else { 1 };
+            let feedback = (self.shift ^ (self.shift >> tap)) & 1;
+            self.shift = (self.shift >> 1) | (feedback << 14);
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.shift & 1 != 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The length of the sequence from the shift register.
+    fn sequence_length(noise: &mut Noise) -> usize {
+        let start = noise.shift;
+        let mut length = 0;
+        loop {
+            for _ in 0..noise.period {
+                noise.clock_timer();
+            }
+            length += 1;
+            if noise.shift == start {
+                return length;
+            }
+        }
+    }
+
+    #[test]
+    fn test_sequence_lengths() {
+        let mut noise = Noise::new(Region::NTSC);
+        assert_eq!(sequence_length(&mut noise), 32767);
+        noise.write(2, 0x80);
+        assert_eq!(sequence_length(&mut noise), 93);
+    }
+
+    #[test]
+    fn test_region_periods() {
+        let mut noise = Noise::new(Region::NTSC);
+        noise.write(2, 0x0f);
+        assert_eq!(noise.period, 4068);
+        let mut noise = Noise::new(Region::PAL);
+        noise.write(2, 0x0f);
+        assert_eq!(noise.period, 3778);
+    }
+
+    #[test]
+    fn test_output() {
+        let mut noise = Noise::new(Region::NTSC);
+        noise.set_enabled(true);
+        noise.write(0, 0x1a);
+        noise.write(3, 0);
+        // The shift register starts at 1, which is silent.
+        assert_eq!(noise.output(), 0);
+        noise.clock_timer();
+        assert_eq!(noise.output(), 0x0a);
+    }
+}
@@ -0,0 +1,230 @@
+//! The two pulse (square wave) channels:
+//! http://wiki.nesdev.com/w/index.php/APU_Pulse
+
+use super::envelope::Envelope;
+use super::length_counter::LengthCounter;
+
+/// The waveforms selected by the duty bits, in the order output.
+const DUTY: [[u8; 8]; 4] = [
+    [0, 1, 0, 0, 0, 0, 0, 0],
+    [0, 1, 1, 0, 0, 0, 0, 0],
+    [0, 1, 1, 1, 1, 0, 0, 0],
+    [1, 0, 0, 1, 1, 1, 1, 1],
+];
+
+/// Which of the pulse channels, as they
+/// differ in how the sweep unit negates.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Channel {
+    One,
+    Two,
+}
+
+/// Bends the pitch by adding or subtracting a shifted
+/// copy of the period: http://wiki.nesdev.com/w/index.php/APU_Sweep
+#[derive(Default)]
+struct Sweep {
+    enabled: bool,
+    period: u8,
+    negate: bool,
+    shift: u8,
+    divider: u8,
+    reload: bool,
+}
+
+pub struct Pulse {
+    channel: Channel,
+    duty: usize,
+    step: usize,
+    // Timer period in APU cycles, less one.
+    period: u16,
+    timer: u16,
+    envelope: Envelope,
+    length: LengthCounter,
+    sweep: Sweep,
+}
+
+impl Pulse {
+    pub fn new(channel: Channel) -> Pulse {
+        Pulse {
+            channel,
+            duty: 0,
+            step: 0,
+            period: 0,
+            timer: 0,
+            envelope: Envelope::default(),
+            length: LengthCounter::default(),
+            sweep: Sweep::default(),
+        }
+    }
+
+    /// Write to one of the channel's 4 registers.
+    pub fn write(&mut self, register: u16, x: u8) {
+        match register {
+            // DDLC VVVV: duty, length halt/envelope loop, envelope.
+            0 => {
+                self.duty = (x >> 6) as usize;
+                self.length.set_halted(x & 0x20 != 0);
+                self.envelope.write(x);
+            },
+            // EPPP NSSS: sweep enable, period, negate and shift.
+            1 => {
+                self.sweep = Sweep {
+                    enabled: x & 0x80 != 0,
+                    period: (x >> 4) & 0b111,
+                    negate: x & 0x08 != 0,
+                    shift: x & 0b111,
+                    divider: self.sweep.divider,
+                    reload: true,
+                };
+            },
+            2 => self.period = (self.period & 0x0700) | x as u16,
+            // LLLL LTTT: length and the high bits of the period,
+            // which also restart the waveform and envelope.
+            3 => {
+                self.period = (self.period & 0x00ff) | ((x as u16 & 0b111) << 8);
+                self.length.load(x);
+                self.step = 0;
+                self.envelope.restart();
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    /// Whether the length counter is still running, as read from 0x4015.
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    /// Clocked every APU cycle, every other CPU cycle.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            self.step = (self.step + 1) % 8;
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        self.envelope.clock();
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+
+        if self.sweep.divider == 0 && self.sweep.enabled
+            && self.sweep.shift > 0 && !self.muted() {
+            self.period = self.target_period();
+        }
+        if self.sweep.divider == 0 || self.sweep.reload {
+            self.sweep.divider = self.sweep.period;
+            self.sweep.reload = false;
+        } else {
+            self.sweep.divider -= 1;
+        }
+    }
+
+    /// The period the sweep unit would change to. Pulse 1 negates
+    /// with ones' complement so subtracts one more than pulse 2.
+    fn target_period(&self) -> u16 {
+        let change = self.period >> self.sweep.shift;
+        if !self.sweep.negate {
+            self.period + change
+        } else {
+            match self.channel {
+                Channel::One => self.period.saturating_sub(change + 1),
+                Channel::Two => self.period - change,
+            }
+        }
+    }
+
+    /// The sweep unit silences the channel when the period is too
+    /// short, or would overflow, even if the sweep is disabled.
+    fn muted(&self) -> bool {
+        self.period < 8 || self.target_period() > 0x7ff
+    }
+
+    /// The current output level, from 0 to 15.
+    pub fn output(&self) -> u8 {
+        if !self.length.active() || self.muted() || DUTY[self.duty][self.step] == 0 {
+            0
+        } else {
+            self.envelope.volume()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An enabled channel at constant volume 15, playing `period`.
+    fn playing(channel: Channel, duty: u8, period: u16) -> Pulse {
+        let mut pulse = Pulse::new(channel);
+        pulse.set_enabled(true);
+        pulse.write(0, duty << 6 | 0x1f);
+        pulse.write(2, period as u8);
+        pulse.write(3, (period >> 8) as u8);
+        pulse
+    }
+
+    fn waveform(pulse: &mut Pulse, period: u16) -> Vec<u8> {
+        (0..8)
+            .map(|_| {
+                let output = pulse.output();
+                for _ in 0..period + 1 {
+                    pulse.clock_timer();
+                }
+                output
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_duty_cycles() {
+        let mut pulse = playing(Channel::One, 2, 0x100);
+        assert_eq!(waveform(&mut pulse, 0x100), vec![0, 15, 15, 15, 15, 0, 0, 0]);
+        let mut pulse = playing(Channel::One, 3, 0x100);
+        assert_eq!(waveform(&mut pulse, 0x100), vec![15, 0, 0, 15, 15, 15, 15, 15]);
+    }
+
+    #[test]
+    fn test_sweep_negate_differs_by_channel() {
+        for &(channel, period) in &[(Channel::One, 0x100 - 0x20 - 1), (Channel::Two, 0x100 - 0x20)] {
+            let mut pulse = playing(channel, 0, 0x100);
+            // Enabled, period 0, negate, shift 3.
+            pulse.write(1, 0x8b);
+            pulse.clock_half_frame();
+            assert_eq!(pulse.period, period);
+        }
+    }
+
+    #[test]
+    fn test_sweep_mutes() {
+        let pulse = playing(Channel::One, 3, 0x007);
+        assert_eq!(pulse.output(), 0);
+        // Even a disabled sweep mutes when it would overflow.
+        let mut pulse = playing(Channel::One, 3, 0x600);
+        pulse.write(1, 0x01);
+        assert_eq!(pulse.output(), 0);
+        pulse.write(1, 0x02);
+        assert_eq!(pulse.output(), 15);
+    }
+
+    #[test]
+    fn test_length_counter_silences() {
+        let mut pulse = playing(Channel::Two, 3, 0x100);
+        assert!(pulse.active());
+        // A length of 10 from the index of 0 in the last write.
+        for _ in 0..10 {
+            pulse.clock_half_frame();
+        }
+        assert!(!pulse.active());
+        assert_eq!(pulse.output(), 0);
+    }
+}
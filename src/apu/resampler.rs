@@ -0,0 +1,195 @@
+//! Band-limited resampling from the CPU clock down to the host's
+//! sample rate, in the style of blip_buf.
+//!
+//! The mixer's output only changes occasionally, so rather than
+//! filtering every cycle each change in level is added to the output
+//! as a band-limited step: the change, spread over a few samples by
+//! a windowed sinc. The output is the running sum of those changes.
+
+use std::f64::consts::PI;
+
+/// Width of the windowed sinc, in output samples.
+const TAPS: usize = 16;
+
+/// Number of positions between two output samples a change can
+/// land at, each of which has its own copy of the kernel.
+const PHASES: usize = 32;
+
+/// Cut off just below the output's Nyquist frequency,
+/// as a fraction of the output sample rate.
+const CUTOFF: f64 = 0.45;
+
+pub struct Resampler {
+    sample_rate: u32,
+    // Output samples per input clock.
+    ratio: f64,
+    // Time of the current clock in output samples,
+    // counted from the start of `deltas`.
+    time: f64,
+    // The change in level each output sample
+    // starting from the oldest not yet read.
+    deltas: Vec<f32>,
+    kernel: Vec<[f32; TAPS]>,
+    level: f32,
+    // Level of the last output sample read.
+    output: f32,
+}
+
+impl Resampler {
+    pub fn new(clock_rate: f64, sample_rate: u32) -> Resampler {
+        Resampler {
+            sample_rate,
+            ratio: sample_rate as f64 / clock_rate,
+            // Leave room for the part of the kernel before a change.
+            time: (TAPS / 2 - 1) as f64,
+            deltas: vec![0.0; TAPS],
+            kernel: kernel(),
+            level: 0.0,
+            output: 0.0,
+        }
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.sample_rate
+    }
+
+    /// Set the level at the current clock.
+    pub fn set_level(&mut self, level: f32) {
+        if level == self.level {
+            return;
+        }
+        let delta = level - self.level;
+        self.level = level;
+
+        let whole = self.time.floor();
+        let phase = ((self.time - whole) * PHASES as f64) as usize;
+        let start = whole as usize + 1 - TAPS / 2;
+        if self.deltas.len() < start + TAPS {
+            self.deltas.resize(start + TAPS, 0.0);
+        }
+        for (i, k) in self.kernel[phase].iter().enumerate() {
+            self.deltas[start + i] += delta * k;
+        }
+    }
+
+    /// Move on to the next clock.
+    pub fn clock(&mut self) {
+        self.time += self.ratio;
+    }
+
+    /// Samples which no later change can affect, ready to be read.
+    pub fn available(&self) -> usize {
+        self.time.floor() as usize + 1 - TAPS / 2
+    }
+
+    /// Read the available samples, passing each through `filter`.
+    pub fn read<F>(&mut self, out: &mut Vec<f32>, mut filter: F)
+        where F: FnMut(f32) -> f32
+    {
+        let available = self.available();
+        self.fill(available);
+        for delta in self.deltas.drain(0..available) {
+            self.output += delta;
+            out.push(filter(self.output));
+        }
+        self.time -= available as f64;
+    }
+
+    /// Drop all but the latest `keep` available samples, for when
+    /// nothing is reading them.
+    pub fn discard(&mut self, keep: usize) {
+        let available = self.available();
+        if available > keep {
+            let excess = available - keep;
+            self.fill(excess);
+            self.output += self.deltas.drain(0..excess).sum::<f32>();
+            self.time -= excess as f64;
+        }
+    }
+
+    /// Make sure there are deltas for the first `samples` samples,
+    /// as none are added while the level holds steady.
+    fn fill(&mut self, samples: usize) {
+        if self.deltas.len() < samples {
+            self.deltas.resize(samples, 0.0);
+        }
+    }
+}
+
+/// A windowed sinc for each phase, each summing to 1
+/// so that a step settles at exactly its new level.
+fn kernel() -> Vec<[f32; TAPS]> {
+    (0..PHASES)
+        .map(|phase| {
+            let offset = phase as f64 / PHASES as f64;
+            let mut taps = [0.0; TAPS];
+            for (i, tap) in taps.iter_mut().enumerate() {
+                // Distance from the change to this output sample.
+                let x = (i as f64 - (TAPS / 2 - 1) as f64) - offset;
+                let sinc = if x == 0.0 {
+                    1.0
+                } else {
+                    (PI * 2.0 * CUTOFF * x).sin() / (PI * 2.0 * CUTOFF * x)
+                };
+                let window = 0.42
+                    + 0.5 * (2.0 * PI * x / TAPS as f64).cos()
+                    + 0.08 * (4.0 * PI * x / TAPS as f64).cos();
+                *tap = (sinc * window) as f32;
+            }
+            let sum: f32 = taps.iter().sum();
+            for tap in &mut taps {
+                *tap /= sum;
+            }
+            taps
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CLOCK_RATE: f64 = 1_789_773.0;
+
+    fn run(resampler: &mut Resampler, clocks: usize, level: f32) {
+        for _ in 0..clocks {
+            resampler.set_level(level);
+            resampler.clock();
+        }
+    }
+
+    #[test]
+    fn test_sample_count() {
+        let mut resampler = Resampler::new(CLOCK_RATE, 44100);
+        let mut out = Vec::new();
+        run(&mut resampler, CLOCK_RATE as usize, 0.0);
+        resampler.read(&mut out, |x| x);
+        assert!((44099..=44100).contains(&out.len()));
+        run(&mut resampler, CLOCK_RATE as usize, 0.0);
+        resampler.read(&mut out, |x| x);
+        assert!((88199..=88200).contains(&out.len()));
+    }
+
+    #[test]
+    fn test_step_settles() {
+        let mut resampler = Resampler::new(CLOCK_RATE, 48000);
+        let mut out = Vec::new();
+        run(&mut resampler, 1000, 0.0);
+        run(&mut resampler, 10000, 0.5);
+        resampler.read(&mut out, |x| x);
+        // Rings a little either side of the step, then holds.
+        assert!(out.iter().all(|&x| (-0.1..=0.6).contains(&x)));
+        assert!(out[out.len() - 100..].iter().all(|&x| (x - 0.5).abs() < 1e-4));
+    }
+
+    #[test]
+    fn test_discard() {
+        let mut resampler = Resampler::new(CLOCK_RATE, 44100);
+        run(&mut resampler, 100_000, 0.25);
+        resampler.discard(10);
+        assert_eq!(resampler.available(), 10);
+        let mut out = Vec::new();
+        resampler.read(&mut out, |x| x);
+        assert!((out[9] - 0.25).abs() < 1e-4);
+    }
+}
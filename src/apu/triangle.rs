@@ -0,0 +1,140 @@
+//! The triangle channel: http://wiki.nesdev.com/w/index.php/APU_Triangle
+
+use super::length_counter::LengthCounter;
+
+/// The 32 step waveform, falling from 15 to 0 and back up.
+const SEQUENCE: [u8; 32] = [
+    15, 14, 13, 12, 11, 10, 9, 8, 7, 6, 5, 4, 3, 2, 1, 0,
+    0, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15,
+];
+
+#[derive(Default)]
+pub struct Triangle {
+    step: usize,
+    period: u16,
+    timer: u16,
+    length: LengthCounter,
+    // Also halts the length counter.
+    control: bool,
+    linear_period: u8,
+    linear_counter: u8,
+    linear_reload: bool,
+}
+
+impl Triangle {
+    pub fn new() -> Triangle {
+        Triangle::default()
+    }
+
+    /// Write to one of the channel's registers at 0x4008-0x400b.
+    pub fn write(&mut self, register: u16, x: u8) {
+        match register {
+            // CRRR RRRR: control and linear counter reload value.
+            0 => {
+                self.control = x & 0x80 != 0;
+                self.length.set_halted(self.control);
+                self.linear_period = x & 0x7f;
+            },
+            1 => (),
+            2 => self.period = (self.period & 0x0700) | x as u16,
+            // LLLL LTTT: length and the high bits of the period.
+            3 => {
+                self.period = (self.period & 0x00ff) | ((x as u16 & 0b111) << 8);
+                self.length.load(x);
+                self.linear_reload = true;
+            },
+            _ => unreachable!(),
+        }
+    }
+
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.length.set_enabled(enabled);
+    }
+
+    pub fn active(&self) -> bool {
+        self.length.active()
+    }
+
+    /// Clocked every CPU cycle, twice the rate of the other
+    /// channels. The waveform only moves while both counters run.
+    pub fn clock_timer(&mut self) {
+        if self.timer == 0 {
+            self.timer = self.period;
+            if self.length.active() && self.linear_counter > 0 {
+                self.step = (self.step + 1) % SEQUENCE.len();
+            }
+        } else {
+            self.timer -= 1;
+        }
+    }
+
+    pub fn clock_quarter_frame(&mut self) {
+        if self.linear_reload {
+            self.linear_counter = self.linear_period;
+        } else if self.linear_counter > 0 {
+            self.linear_counter -= 1;
+        }
+        if !self.control {
+            self.linear_reload = false;
+        }
+    }
+
+    pub fn clock_half_frame(&mut self) {
+        self.length.clock();
+    }
+
+    /// Silencing the channel stops the waveform where it
+    /// is rather than dropping the output to 0.
+    pub fn output(&self) -> u8 {
+        SEQUENCE[self.step]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn playing(linear: u8) -> Triangle {
+        let mut triangle = Triangle::new();
+        triangle.set_enabled(true);
+        triangle.write(0, linear);
+        triangle.write(2, 0);
+        triangle.write(3, 0);
+        triangle.clock_quarter_frame();
+        triangle
+    }
+
+    #[test]
+    fn test_sequence() {
+        let mut triangle = playing(0x7f);
+        let outputs: Vec<u8> = (0..33)
+            .map(|_| {
+                let output = triangle.output();
+                triangle.clock_timer();
+                output
+            })
+            .collect();
+        assert_eq!(&outputs[..], &SEQUENCE.iter().chain(&SEQUENCE[..1]).cloned().collect::<Vec<_>>()[..]);
+    }
+
+    #[test]
+    fn test_linear_counter_stops_sequence() {
+        let mut triangle = playing(2);
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+        triangle.clock_quarter_frame();
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+    }
+
+    #[test]
+    fn test_control_keeps_reloading() {
+        let mut triangle = playing(0x82);
+        for _ in 0..4 {
+            triangle.clock_quarter_frame();
+        }
+        triangle.clock_timer();
+        assert_eq!(triangle.output(), 14);
+    }
+}
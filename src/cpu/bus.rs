@@ -0,0 +1,59 @@
+/// The address bus the CPU is attached to.
+/// Anything implementing this can be driven by `Cpu`,
+/// the NES `Interconnect` being the main example.
+pub trait Bus {
+    fn read(&mut self, addr: u16) -> u8;
+
+    fn write(&mut self, addr: u16, x: u8);
+
+    /// Called once for every cycle the CPU spends
+    /// so that other devices on the bus can keep up.
+    fn tick(&mut self) {}
+
+    /// Called on each of the CPU's read cycles once the bus has been
+    /// clocked, just before the read. DMA can only halt the CPU on a
+    /// read, so this is where the bus can take cycles of its own.
+    fn halt(&mut self) {}
+
+    fn read_u16(&mut self, addr: u16) -> u16 {
+        let lo = self.read(addr);
+        let hi = self.read(addr.wrapping_add(1));
+        (lo as u16) | ((hi as u16) << 8)
+    }
+}
+
+/// A flat 64K of RAM with nothing else attached,
+/// useful for running the CPU in isolation.
+pub struct Ram {
+    mem: Vec<u8>,
+}
+
+impl Ram {
+    pub fn new() -> Ram {
+        Ram {
+            mem: vec![0; 0x10000],
+        }
+    }
+
+    /// Copy `data` into memory starting at `addr`.
+    pub fn load(&mut self, addr: u16, data: &[u8]) {
+        let start = addr as usize;
+        self.mem[start..start + data.len()].copy_from_slice(data);
+    }
+}
+
+impl Default for Ram {
+    fn default() -> Ram {
+        Ram::new()
+    }
+}
+
+impl Bus for Ram {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.mem[addr as usize]
+    }
+
+    fn write(&mut self, addr: u16, x: u8) {
+        self.mem[addr as usize] = x;
+    }
+}
@@ -1,14 +1,14 @@
 //! A 6502 CPU Emulator
 
 use super::instruction::{AddressMode, Instruction, Mnemonic};
-use super::super::interconnect::{Interconnect};
+use super::bus::Bus;
 
 /// A struct holding all of the Registers
 /// belonging to the 6502.
 #[derive(Debug, Eq, PartialEq)]
 pub struct Registers {
     pub pc: u16, // Program Counter
-    pub sp: u16, // Stack Pointer
+    pub sp: u8, // Stack Pointer, an offset into page 1
     pub a: u8, // Accumulator
     pub x: u8, // General purpose register
     pub y: u8, // General purpose register
@@ -26,7 +26,13 @@ impl Registers {
     }
 }
 
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+impl Default for Registers {
+    fn default() -> Registers {
+        Registers::new()
+    }
+}
+
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
 pub struct Flags {
     pub n: bool,
     pub z: bool,
@@ -34,15 +40,15 @@ pub struct Flags {
     pub i: bool,
     pub d: bool,
     pub v: bool,
-    pub b: bool,
 }
 
 impl Flags {
+    /// Bits 4 (B) and 5 of the status byte do not exist
+    /// in the register itself and are ignored.
     pub fn from_byte(byte: u8) -> Flags {
         Flags {
             n: (byte & 0b10000000) != 0,
             v: (byte & 0b01000000) != 0,
-            b: (byte & 0b00010000) != 0,
             d: (byte & 0b00001000) != 0,
             i: (byte & 0b00000100) != 0,
             z: (byte & 0b00000010) != 0,
@@ -50,38 +56,20 @@ impl Flags {
         }
     }
 
-    pub fn from_value_nz(x: u8) -> Flags {
-        Flags {
-            n: (x & 0x80) != 0,
-            z: x == 0,
-            .. Default::default()
-        }
-    }
-
-    pub fn from_value_zc(x: u16) -> Flags {
-        Flags {
-            z: x == 0,
-            c: (x & 0xff00) != 0,
-            .. Default::default()
-        }
-    }
-
-    pub fn from_value_nzc(x: u16) -> Flags {
-        Flags {
-            n: (x & 0x80) != 0,
-            .. Flags::from_value_zc(x)
-        }
-    }
-
-    pub fn from_value_nzcv(x: u16) -> Flags {
-        panic!("Implement setting v flag");
-        Flags::from_value_nzc(x)
+    /// Set the N and Z flags from the result of an operation.
+    #[inline(always)]
+    pub fn set_nz(&mut self, x: u8) {
+        self.n = (x & 0x80) != 0;
+        self.z = x == 0;
     }
 
+    /// The status register as it appears when pushed to the stack
+    /// by an interrupt. Bit 5 is always set and the B flag is left
+    /// clear; BRK and PHP set it on the pushed copy themselves.
     pub fn as_byte(&self) -> u8 {
         (self.n as u8) << 7 |
         (self.v as u8) << 6 |
-        (self.b as u8) << 4 |
+        1 << 5 |
         (self.d as u8) << 3 |
         (self.i as u8) << 2 |
         (self.z as u8) << 1 |
@@ -89,27 +77,80 @@ impl Flags {
     }
 }
 
-impl Default for Flags {
-    fn default() -> Flags {
-        Flags {
-            n: false,
-            z: false,
-            c: false,
-            i: false,
-            d: false,
-            v: false,
-            b: false,
-        }
-    }
-}
+/// B flag as set on the status byte pushed by BRK and PHP.
+const BREAK_FLAG: u8 = 0b00010000;
+
+const NMI_VECTOR: u16 = 0xfffa;
+const RESET_VECTOR: u16 = 0xfffc;
+const IRQ_VECTOR: u16 = 0xfffe;
 
 fn pages_differ(a: u16, b: u16) -> bool {
     a & 0xff00 != b & 0xff00
 }
 
+/// How an instruction uses the memory operand it addresses.
+/// Indexed modes only skip the cycle spent fixing up the high byte
+/// of the address for reads that stay within a page, every other
+/// access always pays for it with a dummy read.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+enum Access {
+    Read,
+    Write,
+    Modify,
+}
+
+// Shifts and rotates shared by the read-modify-write
+// instructions, official and unofficial alike.
+
+fn asl(flags: &mut Flags, value: u8) -> u8 {
+    let result = value << 1;
+    flags.c = (value & 0x80) != 0;
+    flags.set_nz(result);
+    result
+}
+
+fn lsr(flags: &mut Flags, value: u8) -> u8 {
+    let result = value >> 1;
+    flags.c = (value & 0x01) != 0;
+    flags.set_nz(result);
+    result
+}
+
+fn rol(flags: &mut Flags, value: u8) -> u8 {
+    let result = (value << 1) | (flags.c as u8);
+    flags.c = (value & 0x80) != 0;
+    flags.set_nz(result);
+    result
+}
+
+fn ror(flags: &mut Flags, value: u8) -> u8 {
+    let result = (value >> 1) | ((flags.c as u8) << 7);
+    flags.c = (value & 0x01) != 0;
+    flags.set_nz(result);
+    result
+}
+
 pub struct Cpu {
     pub reg: Registers,
     pub flags: Flags,
+    // Level of the NMI line as of the last call to `set_nmi`.
+    nmi: bool,
+    // Set on the rising edge of the NMI line and cleared
+    // once the interrupt has been serviced.
+    nmi_pending: bool,
+    // Level of the IRQ line, which stays asserted
+    // until the device that raised it acknowledges.
+    irq: bool,
+    // Address of the KIL opcode that jammed the CPU.
+    halted: Option<u16>,
+    // Total number of cycles run since power up.
+    cycles: u64,
+}
+
+impl Default for Cpu {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Cpu {
@@ -117,636 +158,954 @@ impl Cpu {
         Cpu {
             reg: Registers::new(),
             flags: Default::default(),
+            nmi: false,
+            nmi_pending: false,
+            irq: false,
+            halted: None,
+            cycles: 0,
         }
     }
 
     /// Reset the CPU: http://wiki.nesdev.com/w/index.php/CPU_power_up_state
-    pub fn reset(&mut self, interconnect: &mut Interconnect) {
-        self.reg.pc = interconnect.cpu_read_u16(0xfffc);
+    pub fn reset<B: Bus>(&mut self, mem: &mut B) {
+        // The reset sequence runs through the same 7 cycles as an
+        // interrupt, but the writes to the stack become reads.
+        let pc = self.reg.pc;
+        self.read(mem, pc);
+        self.read(mem, pc);
+        for offset in 0..3 {
+            let sp = self.reg.sp.wrapping_sub(offset);
+            self.read(mem, 0x0100 | sp as u16);
+        }
+        self.reg.pc = self.read_u16(mem, RESET_VECTOR);
         self.reg.sp = 0xfd;
         self.flags = Flags::from_byte(0x24);
+        self.nmi_pending = false;
+        self.halted = None;
+    }
+
+    /// The number of cycles the CPU has run since power up.
+    pub fn cycles(&self) -> u64 {
+        self.cycles
+    }
+
+    /// If the CPU has executed one of the KIL opcodes it locks up
+    /// until the next reset, this returns the address of that opcode.
+    pub fn halted(&self) -> Option<u16> {
+        self.halted
+    }
+
+    /// Drive the NMI line. The NMI is edge-triggered so an
+    /// interrupt is only taken when the line goes from low to high,
+    /// holding it high will not trigger any further interrupts.
+    pub fn set_nmi(&mut self, level: bool) {
+        if level && !self.nmi {
+            self.nmi_pending = true;
+        }
+        self.nmi = level;
+    }
+
+    /// Drive the IRQ line. The IRQ is level-triggered and is
+    /// taken before every instruction for as long as the line
+    /// is held high and interrupts are not disabled.
+    pub fn set_irq(&mut self, level: bool) {
+        self.irq = level;
+    }
+
+    /// Push the PC and status register and jump through
+    /// the given interrupt vector.
+    fn interrupt<B: Bus>(&mut self, mem: &mut B, vector: u16) {
+        // The opcode fetch is replaced by two dummy reads.
+        let pc = self.reg.pc;
+        self.read(mem, pc);
+        self.read(mem, pc);
+        self.push_u16(mem, pc);
+        let status = self.flags.as_byte();
+        self.push(mem, status);
+        self.flags.i = true;
+        self.reg.pc = self.read_u16(mem, vector);
+    }
+
+    /// Service any pending interrupt, NMI taking priority over IRQ.
+    /// Returns whether an interrupt was taken.
+    fn poll_interrupts<B: Bus>(&mut self, mem: &mut B) -> bool {
+        if self.nmi_pending {
+            self.nmi_pending = false;
+            self.interrupt(mem, NMI_VECTOR);
+            true
+        } else if self.irq && !self.flags.i {
+            self.interrupt(mem, IRQ_VECTOR);
+            true
+        } else {
+            false
+        }
+    }
+
+    /// Spend a single cycle, letting the rest of the bus keep up.
+    #[inline(always)]
+    fn tick<B: Bus>(&mut self, mem: &mut B) {
+        mem.tick();
+        self.cycles += 1;
+    }
+
+    /// Every bus access takes a cycle, the bus being clocked
+    /// before the access lands so that other devices see it at
+    /// the end of the cycle as they would on the real hardware.
+    #[inline(always)]
+    fn read<B: Bus>(&mut self, mem: &mut B, addr: u16) -> u8 {
+        self.tick(mem);
+        mem.halt();
+        mem.read(addr)
+    }
+
+    #[inline(always)]
+    fn write<B: Bus>(&mut self, mem: &mut B, addr: u16, x: u8) {
+        self.tick(mem);
+        mem.write(addr, x);
+    }
+
+    fn read_u16<B: Bus>(&mut self, mem: &mut B, addr: u16) -> u16 {
+        let lo = self.read(mem, addr);
+        let hi = self.read(mem, addr.wrapping_add(1));
+        (lo as u16) | ((hi as u16) << 8)
     }
 
     /// Read the next byte of memory and advance
     /// the PC Register by the same amount.
     #[inline(always)]
-    fn next(&mut self, mem: &mut Interconnect) -> u8 {
-        let x = self.peek(mem);
-        self.reg.pc += 1;
+    fn next<B: Bus>(&mut self, mem: &mut B) -> u8 {
+        let pc = self.reg.pc;
+        let x = self.read(mem, pc);
+        self.reg.pc = pc.wrapping_add(1);
         x
     }
 
     /// Read the next two bytes of memory and advance
     /// the PC Register by the same amount.
     #[inline(always)]
-    fn next_u16(&mut self, mem: &mut Interconnect) -> u16 {
+    fn next_u16<B: Bus>(&mut self, mem: &mut B) -> u16 {
         let lo = self.next(mem);
         let hi = self.next(mem);
         (lo as u16) | ((hi as u16) << 8)
     }
 
-    /// Peek at the following byte of memory.
+    /// Peek at the following byte of memory. Peeking
+    /// does not take any cycles, it is for debugging only.
     #[inline(always)]
-    pub fn peek(&self, mem: &mut Interconnect) -> u8 {
-        mem.cpu_read(self.reg.pc)
+    pub fn peek<B: Bus>(&self, mem: &mut B) -> u8 {
+        mem.read(self.reg.pc)
     }
 
     /// Peek at the following two bytes of memory.
     #[inline(always)]
-    pub fn peek_u16(&self, mem: &mut Interconnect) -> u16 {
+    pub fn peek_u16<B: Bus>(&self, mem: &mut B) -> u16 {
         let lo = self.peek(mem);
         let hi = self.skip_peek(1, mem);
         (lo as u16) | ((hi as u16) << 8)
     }
 
-    pub fn skip_peek(&self, skip: usize, mem: &mut Interconnect) -> u8 {
-        mem.cpu_read(self.reg.pc + skip as u16)
+    pub fn skip_peek<B: Bus>(&self, skip: usize, mem: &mut B) -> u8 {
+        mem.read(self.reg.pc.wrapping_add(skip as u16))
     }
 
-    pub fn skip_peek_u16(&self, skip: usize, mem: &mut Interconnect) -> u16 {
+    pub fn skip_peek_u16<B: Bus>(&self, skip: usize, mem: &mut B) -> u16 {
         let lo = self.skip_peek(skip, mem);
         let hi = self.skip_peek(skip + 1, mem);
         (lo as u16) | ((hi as u16) << 8)
     }
 
-    fn absolute(&mut self, mem: &mut Interconnect) -> u16 {
+    fn absolute<B: Bus>(&mut self, mem: &mut B) -> u16 {
         self.next_u16(mem)
     }
 
-    fn indirect(&mut self, mem: &mut Interconnect) -> u16 {
+    /// Indirect Addressing Mode (JMP only)
+    /// The 6502 never carries into the high byte when
+    /// fetching the pointer, so a pointer at $xxff reads
+    /// its high byte from $xx00.
+    fn indirect<B: Bus>(&mut self, mem: &mut B) -> u16 {
         let base = self.next_u16(mem);
-        let lo = mem.cpu_read(base);
-        let hi = mem.cpu_read(base + 1);
+        let lo = self.read(mem, base);
+        let hi = self.read(mem, (base & 0xff00) | (base.wrapping_add(1) & 0x00ff));
         (lo as u16) | ((hi as u16) << 8)
     }
 
-    fn x_indexed_indirect(&self, mem: &mut Interconnect, base: u8) -> u16 {
-        let indirect = base.wrapping_add(self.reg.x);
-        let lo = mem.cpu_read(indirect as u16);
-        let hi = mem.cpu_read(indirect.wrapping_add(1) as u16);
+    /// Read a pointer from the zero page, which
+    /// wraps around without leaving it.
+    fn zero_page_pointer<B: Bus>(&mut self, mem: &mut B, base: u8) -> u16 {
+        let lo = self.read(mem, base as u16);
+        let hi = self.read(mem, base.wrapping_add(1) as u16);
         (lo as u16) | ((hi as u16) << 8)
     }
 
-    fn indirect_indexed(&self, mem: &mut Interconnect, base: u8) -> (u16, isize) {
-        let lo = mem.cpu_read(base as u16);
-        let hi = mem.cpu_read(base.wrapping_add(1) as u16);
-        let addr = ((lo as u16) | ((hi as u16) << 8)).wrapping_add(self.reg.y as u16);
-        let cycles = if pages_differ(addr, addr - self.reg.y as u16) { 1 } else { 0 };
-        (addr, cycles)
-    }
-
-    fn zero_page_indexed_x(&self, mem: &Interconnect, base: u8) -> u16 {
-        base.wrapping_add(self.reg.x) as u16
+    fn x_indexed_indirect<B: Bus>(&mut self, mem: &mut B, base: u8) -> u16 {
+        // The base is read while X is added to it.
+        self.read(mem, base as u16);
+        let indirect = base.wrapping_add(self.reg.x);
+        self.zero_page_pointer(mem, indirect)
     }
 
-    fn zero_page_indexed_y(&self, mem: &Interconnect, base: u8) -> u16 {
-        base.wrapping_add(self.reg.y) as u16
+    fn indirect_indexed<B: Bus>(&mut self, mem: &mut B, base: u8, access: Access) -> u16 {
+        let base = self.zero_page_pointer(mem, base);
+        let y = self.reg.y;
+        self.indexed(mem, base, y, access)
     }
 
-    fn indexed_absolute_x(&self, mem: &Interconnect, base: u16) -> (u16, isize) {
-        let addr = base.wrapping_add(self.reg.x as u16);
-        let cycles = if pages_differ(base, addr) { 1 } else { 0 };
-        (addr, cycles)
+    fn zero_page_indexed<B: Bus>(&mut self, mem: &mut B, base: u8, index: u8) -> u16 {
+        // The base is read while the index is added to it.
+        self.read(mem, base as u16);
+        base.wrapping_add(index) as u16
     }
 
-    fn indexed_absolute_y(&self, mem: &Interconnect, base: u16) -> (u16, isize) {
-        let addr = base.wrapping_add(self.reg.y as u16);
-        let cycles = if pages_differ(base, addr) { 1 } else { 0 };
-        (addr, cycles)
+    /// Add an index to an absolute address. The low byte is added
+    /// first and the CPU reads from the unfixed address while it
+    /// carries into the high byte, which it can skip for reads
+    /// that stay on the same page.
+    fn indexed<B: Bus>(&mut self, mem: &mut B, base: u16, index: u8, access: Access) -> u16 {
+        let addr = base.wrapping_add(index as u16);
+        if pages_differ(base, addr) || access != Access::Read {
+            self.read(mem, (base & 0xff00) | (addr & 0x00ff));
+        }
+        addr
     }
 
     /// Relative Addressing Mode
     /// The operand 'offset' is interpreted
     /// as a signed byte and added to the
     /// current PC to give the final address.
-    fn relative(&self, offset: u8) -> (u16, isize) {
-        let addr = ((self.reg.pc as i32) + (offset as i8) as i32) as u16;
-        let cycles = if pages_differ(self.reg.pc, addr) { 2 } else { 1 };
-        (addr, cycles)
+    fn relative(&self, offset: u8) -> u16 {
+        self.reg.pc.wrapping_add(offset as i8 as u16)
+    }
+
+    /// Fetch the branch offset and take the branch if `cond` holds.
+    /// A taken branch costs an extra cycle, plus one more if
+    /// it lands on a different page, each spent on a dummy read.
+    fn branch<B: Bus>(&mut self, mem: &mut B, cond: bool) {
+        let offset = self.next(mem);
+        if cond {
+            let pc = self.reg.pc;
+            let addr = self.relative(offset);
+            self.read(mem, pc);
+            if pages_differ(pc, addr) {
+                self.read(mem, (pc & 0xff00) | (addr & 0x00ff));
+            }
+            self.reg.pc = addr;
+        }
     }
 
-    fn php(&mut self, mem: &mut Interconnect) {
-        let sp = self.flags.as_byte();
-        self.push(mem, sp);
+    fn php<B: Bus>(&mut self, mem: &mut B) {
+        let status = self.flags.as_byte() | BREAK_FLAG;
+        self.push(mem, status);
     }
 
     fn cmp(&mut self, x: u8, y: u8) {
-        let value = (x as u16) - (y as u16);
-        self.flags = Flags::from_value_nzc(value);
+        let value = x.wrapping_sub(y);
+        self.flags.c = x >= y;
+        self.flags.set_nz(value);
+    }
+
+    /// Add with carry, setting overflow when the sign of the
+    /// result differs from the sign of both operands.
+    /// The NES' 2A03 has no decimal mode so the D flag is ignored.
+    fn adc(&mut self, value: u8) {
+        let a = self.reg.a;
+        let sum = (a as u16) + (value as u16) + (self.flags.c as u16);
+        let result = sum as u8;
+        self.flags.c = sum > 0xff;
+        self.flags.v = ((a ^ result) & (value ^ result) & 0x80) != 0;
+        self.flags.set_nz(result);
+        self.reg.a = result;
     }
 
-    fn push(&mut self, mem: &mut Interconnect, x: u8) {
-        mem.cpu_write(self.reg.sp, x);
-        self.reg.sp -= 1;
+    /// Subtract with borrow is an add of the one's complement.
+    fn sbc(&mut self, value: u8) {
+        self.adc(!value);
     }
 
-    fn push_u16(&mut self, mem: &mut Interconnect, x: u16) {
+    /// The stack lives in page 1 and grows downwards, the
+    /// stack pointer pointing at the next free slot and
+    /// wrapping around within the page.
+    fn push<B: Bus>(&mut self, mem: &mut B, x: u8) {
+        let sp = self.reg.sp;
+        self.write(mem, 0x0100 | sp as u16, x);
+        self.reg.sp = sp.wrapping_sub(1);
+    }
+
+    fn push_u16<B: Bus>(&mut self, mem: &mut B, x: u16) {
         let lo = x as u8;
         let hi = (x >> 8) as u8;
         self.push(mem, hi);
         self.push(mem, lo);
     }
 
-    fn pop(&mut self, mem: &mut Interconnect) -> u8 {
-        let value = mem.cpu_read(self.reg.sp);
-        self.reg.sp += 1;
-        value
+    /// Spend a cycle reading the top of the stack without moving
+    /// the stack pointer, as JSR and the instructions pulling
+    /// from the stack do before anything else.
+    fn read_stack<B: Bus>(&mut self, mem: &mut B) {
+        let sp = self.reg.sp;
+        self.read(mem, 0x0100 | sp as u16);
+    }
+
+    fn pop<B: Bus>(&mut self, mem: &mut B) -> u8 {
+        self.reg.sp = self.reg.sp.wrapping_add(1);
+        let sp = self.reg.sp;
+        self.read(mem, 0x0100 | sp as u16)
     }
 
-    fn pop_u16(&mut self, mem: &mut Interconnect) -> u16 {
+    fn pop_u16<B: Bus>(&mut self, mem: &mut B) -> u16 {
         let lo = self.pop(mem);
         let hi = self.pop(mem);
         (lo as u16) | ((hi as u16) << 8)
     }
 
-    fn get_address_and_value(&mut self, mem: &mut Interconnect, address_mode: AddressMode) -> (Option<u16>, u8, isize) {
+    /// Resolve the effective address of the operand, spending the
+    /// extra cycle indexed modes take to cross a page as needed
+    /// for the given kind of access.
+    fn get_address<B: Bus>(&mut self, mem: &mut B, address_mode: AddressMode, access: Access) -> u16 {
         match address_mode {
-            AddressMode::Accumulator => (None, self.reg.a, 0),
             AddressMode::Absolute => {
-                let addr = self.absolute(mem);
-                (Some(addr), mem.cpu_read(addr), 0)
+                self.absolute(mem)
             },
             AddressMode::AbsoluteXIndexed => {
                 let base = self.absolute(mem);
-                let (addr, cycles) = self.indexed_absolute_x(mem, base);
-                (Some(addr), mem.cpu_read(addr), cycles)
+                let x = self.reg.x;
+                self.indexed(mem, base, x, access)
             },
             AddressMode::AbsoluteYIndexed => {
                 let base = self.absolute(mem);
-                let (addr, cycles) = self.indexed_absolute_y(mem, base);
-                (Some(addr), mem.cpu_read(addr), cycles)
-            },
-            AddressMode::Immediate => {
-                (None, self.next(mem), 0)
+                let y = self.reg.y;
+                self.indexed(mem, base, y, access)
             },
             AddressMode::Indirect => {
-                let addr = self.indirect(mem);
-                (Some(addr), mem.cpu_read(addr), 0)
+                self.indirect(mem)
             },
             AddressMode::XIndexedIndirect => {
                 let base = self.next(mem);
-                let addr = self.x_indexed_indirect(mem, base);
-                (Some(addr), mem.cpu_read(addr), 0)
+                self.x_indexed_indirect(mem, base)
             },
             AddressMode::IndirectYIndexed => {
                 let base = self.next(mem);
-                let (addr, cycles) = self.indirect_indexed(mem, base);
-                (Some(addr), mem.cpu_read(addr), cycles)
-            },
-            AddressMode::Relative => {
-                let offset = self.next(mem);
-                let (addr, cycles) = self.relative(offset);
-                (Some(addr), mem.cpu_read(addr), cycles)
+                self.indirect_indexed(mem, base, access)
             },
             AddressMode::ZeroPage => {
-                let addr = self.next(mem) as u16;
-                (Some(addr), mem.cpu_read(addr), 0)
+                self.next(mem) as u16
             },
             AddressMode::ZeroPageXIndexed => {
                 let base = self.next(mem);
-                let addr = self.zero_page_indexed_x(mem, base);
-                (Some(addr), mem.cpu_read(addr), 0)
+                let x = self.reg.x;
+                self.zero_page_indexed(mem, base, x)
             },
             AddressMode::ZeroPageYIndexed => {
                 let base = self.next(mem);
-                let addr = self.zero_page_indexed_y(mem, base);
-                (Some(addr), mem.cpu_read(addr), 0)
+                let y = self.reg.y;
+                self.zero_page_indexed(mem, base, y)
             },
-            _ => panic!("No address or value for AddressMode: `{:?}`", address_mode)
+            _ => panic!("No address for mode: {:?}", address_mode),
         }
     }
 
-    fn get_address(&mut self, mem: &mut Interconnect, address_mode: AddressMode) -> (u16, isize) {
+    fn get_address_value<B: Bus>(&mut self, mem: &mut B, address_mode: AddressMode) -> u8 {
         match address_mode {
-            AddressMode::Absolute => {
-                (self.absolute(mem), 0)
-            },
-            AddressMode::AbsoluteXIndexed => {
-                let base = self.absolute(mem);
-                self.indexed_absolute_x(mem, base)
-            },
-            AddressMode::AbsoluteYIndexed => {
-                let base = self.absolute(mem);
-                self.indexed_absolute_y(mem, base)
-            },
-            AddressMode::Indirect => {
-                (self.indirect(mem), 0)
-            },
-            AddressMode::XIndexedIndirect => {
-                let base = self.next(mem);
-                (self.x_indexed_indirect(mem, base), 0)
-            },
-            AddressMode::IndirectYIndexed => {
-                let base = self.next(mem);
-                self.indirect_indexed(mem, base)
-            },
-            AddressMode::Relative => {
-                let offset = self.next(mem);
-                self.relative(offset)
-            },
-            AddressMode::ZeroPage => {
-                (self.next(mem) as u16, 0)
-            },
-            AddressMode::ZeroPageXIndexed => {
-                let base = self.next(mem);
-                (self.zero_page_indexed_x(mem, base), 0)
-            },
-            AddressMode::ZeroPageYIndexed => {
-                let base = self.next(mem);
-                (self.zero_page_indexed_y(mem, base), 0)
+            AddressMode::Accumulator => self.reg.a,
+            AddressMode::Immediate => self.next(mem),
+            _ => {
+                let addr = self.get_address(mem, address_mode, Access::Read);
+                self.read(mem, addr)
             },
-            _ => panic!("No address for mode: {:?}", address_mode),
         }
     }
 
-    fn get_address_value(&mut self, mem: &mut Interconnect, address_mode: AddressMode) -> (u8, isize) {
-        let (_, value, page_cycles) = self.get_address_and_value(mem, address_mode);
-        (value, page_cycles)
+    /// Store a register to the operand's address.
+    fn store<B: Bus>(&mut self, mem: &mut B, address_mode: AddressMode, value: u8) {
+        let addr = self.get_address(mem, address_mode, Access::Write);
+        self.write(mem, addr, value);
     }
 
-    fn with_address_modify<F>(&mut self, mem: &mut Interconnect, address_mode: AddressMode, f: F) -> isize
-        where F: Fn(u8) -> (u8, Flags) {
-        // TODO: also accept a bit mask that determines what flags are to be set
-        // based on the result of the given function.
+    /// Apply `f` to the operand of a read-modify-write instruction,
+    /// writing the result back to either the accumulator or memory
+    /// and returning it. Memory operands are written back twice,
+    /// first unmodified while `f` is applied.
+    fn with_address_modify<B: Bus, F>(&mut self, mem: &mut B, address_mode: AddressMode, f: F) -> u8
+        where F: FnOnce(&mut Flags, u8) -> u8 {
         match address_mode {
             AddressMode::Accumulator => {
-                let (value, flags) = f(self.reg.a);
-                self.reg.a = value;
-                self.flags = flags;
-                0
-            },
-            AddressMode::Immediate => {
-                let value = self.next(mem);
-                f(value);
-                0
+                self.reg.a = f(&mut self.flags, self.reg.a);
+                self.reg.a
             },
             _ => {
-                let (addr, value, page_cycles) = self.get_address_and_value(mem, address_mode);
-                let (value, flags) = f(value);
-                mem.cpu_write(addr.unwrap(), value);
-                self.flags = flags;
-                page_cycles
+                let addr = self.get_address(mem, address_mode, Access::Modify);
+                let value = self.read(mem, addr);
+                self.write(mem, addr, value);
+                let value = f(&mut self.flags, value);
+                self.write(mem, addr, value);
+                value
             },
         }
     }
 
-    pub fn current_instruction(&self, mem: &mut Interconnect) -> Instruction {
-        let code = mem.cpu_read(self.reg.pc);
+    /// The unstable SHA, SHX, SHY and TAS stores, which AND the value
+    /// with the high byte of the base address plus one. If indexing
+    /// crosses a page the high byte of the target address is also
+    /// replaced by the value written.
+    fn unstable_store<B: Bus>(&mut self, mem: &mut B, address_mode: AddressMode, value: u8) {
+        let addr = self.get_address(mem, address_mode, Access::Write);
+        let index = match address_mode {
+            AddressMode::AbsoluteXIndexed => self.reg.x,
+            _ => self.reg.y,
+        };
+        let base = addr.wrapping_sub(index as u16);
+        let value = value & ((base >> 8) as u8).wrapping_add(1);
+        let addr = if pages_differ(base, addr) {
+            ((value as u16) << 8) | (addr & 0x00ff)
+        } else {
+            addr
+        };
+        self.write(mem, addr, value);
+    }
+
+    pub fn current_instruction<B: Bus>(&self, mem: &mut B) -> Instruction {
+        let code = mem.read(self.reg.pc);
         Instruction::from_code(code)
     }
 
-    pub fn next_instruction(&mut self, mem: &mut Interconnect) -> Instruction {
+    pub fn next_instruction<B: Bus>(&mut self, mem: &mut B) -> Instruction {
         let code = self.next(mem);
         Instruction::from_code(code)
     }
 
-    pub fn step(&mut self, mem: &mut Interconnect) -> isize {
+    /// Run a single instruction, or the interrupt sequence if an
+    /// interrupt is pending, and return the number of cycles taken.
+    /// The bus is ticked once for every one of those cycles, as each
+    /// memory access happens, including the dummy reads and writes.
+    pub fn step<B: Bus>(&mut self, mem: &mut B) -> isize {
+        let start = self.cycles;
+        if self.halted.is_some() {
+            // A jammed CPU does nothing but the clock keeps running.
+            self.tick(mem);
+        } else if !self.poll_interrupts(mem) {
+            self.execute(mem);
+        }
+        (self.cycles - start) as isize
+    }
+
+    fn execute<B: Bus>(&mut self, mem: &mut B) {
 //        println!("{}", self.reg.pc);
         let instruction = self.next_instruction(mem);
 //        println!("{:#x} {:?}", instruction.code, instruction.mnemonic);
-        let page_cycles = match instruction.mnemonic {
+        match instruction.address_mode {
+            // Single byte instructions read the following byte
+            // anyway, and discard it.
+            AddressMode::Implied | AddressMode::Accumulator => {
+                let pc = self.reg.pc;
+                self.read(mem, pc);
+            },
+            _ => {},
+        }
+        match instruction.mnemonic {
             Mnemonic::ADC => {
-                let a = self.reg.a as u16;
-                let c = self.flags.c as u16;
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = a + value as u16 + c;
-                    (value as u8, Flags::from_value_nzcv(value))
-                })
+                let value = self.get_address_value(mem, instruction.address_mode);
+                self.adc(value);
             },
             Mnemonic::AND => {
-                let a = self.reg.a;
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = a & value;
-                    (value, Flags::from_value_nz(value))
-                })
+                let value = self.get_address_value(mem, instruction.address_mode);
+                self.reg.a &= value;
+                self.flags.set_nz(self.reg.a);
             },
             Mnemonic::ASL => {
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = (value as u16) << 1;
-                    (value as u8, Flags::from_value_nzc(value))
-                })
+                self.with_address_modify(mem, instruction.address_mode, asl);
             },
             Mnemonic::BCC => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if !self.flags.c {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = !self.flags.c;
+                self.branch(mem, cond);
             },
             Mnemonic::BCS => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if self.flags.c {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = self.flags.c;
+                self.branch(mem, cond);
             },
             Mnemonic::BEQ => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if self.flags.z {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = self.flags.z;
+                self.branch(mem, cond);
             },
             Mnemonic::BIT => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 self.flags.n = (value & 0b10000000) != 0;
                 self.flags.v = (value & 0b01000000) != 0;
-                self.flags.z = (self.reg.a & value) != 0;
-                page_cycles
+                self.flags.z = (self.reg.a & value) == 0;
             },
             Mnemonic::BMI => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if self.flags.n {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = self.flags.n;
+                self.branch(mem, cond);
             },
             Mnemonic::BNE => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if !self.flags.z {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = !self.flags.z;
+                self.branch(mem, cond);
             },
             Mnemonic::BPL => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if !self.flags.n {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = !self.flags.n;
+                self.branch(mem, cond);
             },
             Mnemonic::BRK => {
-                // Disable interrupts
-                self.flags.i = true;
-
-                let pc = self.reg.pc;
+                // BRK is followed by a padding byte which
+                // is skipped over by the return address.
+                let pc = self.reg.pc.wrapping_add(1);
                 self.push_u16(mem, pc);
                 self.php(mem);
-                self.reg.pc = mem.cpu_read_u16(0xfffe);
-                0
+                // Disable interrupts
+                self.flags.i = true;
+                self.reg.pc = self.read_u16(mem, IRQ_VECTOR);
             },
             Mnemonic::BVC => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if !self.flags.v {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = !self.flags.v;
+                self.branch(mem, cond);
             },
             Mnemonic::BVS => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                if self.flags.v {
-                    self.reg.pc = addr;
-                }
-                page_cycles
+                let cond = self.flags.v;
+                self.branch(mem, cond);
             },
             Mnemonic::CLC => {
                 self.flags.c = false;
-                0
             },
             Mnemonic::CLD => {
                 self.flags.d = false;
-                0
             },
             Mnemonic::CLI => {
                 self.flags.i = false;
-                0
             },
             Mnemonic::CLV => {
                 self.flags.v = false;
-                0
             },
             Mnemonic::CMP => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 let a = self.reg.a;
                 self.cmp(a, value);
-                page_cycles
             },
             Mnemonic::CPX => {
                 let x = self.reg.x;
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 self.cmp(x, value);
-                page_cycles
             },
             Mnemonic::CPY => {
                 let y = self.reg.y;
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 self.cmp(y, value);
-                page_cycles
             },
             Mnemonic::DEC => {
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = value.wrapping_sub(1);
-                    (value, Flags::from_value_nz(value))
-                })
+                self.with_address_modify(mem, instruction.address_mode, |flags, value| {
+                    let result = value.wrapping_sub(1);
+                    flags.set_nz(result);
+                    result
+                });
             },
             Mnemonic::DEX => {
                 self.reg.x = self.reg.x.wrapping_sub(1);
-                self.flags = Flags::from_value_nz(self.reg.x);
-                0
+                self.flags.set_nz(self.reg.x);
             },
             Mnemonic::DEY => {
                 self.reg.y = self.reg.y.wrapping_sub(1);
-                self.flags = Flags::from_value_nz(self.reg.y);
-                0
+                self.flags.set_nz(self.reg.y);
             },
             Mnemonic::EOR => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
-                self.reg.a = value ^ self.reg.a;
-                self.flags = Flags::from_value_nz(self.reg.a);
-                page_cycles
+                let value = self.get_address_value(mem, instruction.address_mode);
+                self.reg.a ^= value;
+                self.flags.set_nz(self.reg.a);
             },
             Mnemonic::INC => {
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = value.wrapping_add(1);
-                    (value, Flags::from_value_nz(value))
-                })
+                self.with_address_modify(mem, instruction.address_mode, |flags, value| {
+                    let result = value.wrapping_add(1);
+                    flags.set_nz(result);
+                    result
+                });
             },
             Mnemonic::INX => {
                 self.reg.x = self.reg.x.wrapping_add(1);
-                self.flags = Flags::from_value_nz(self.reg.x);
-                0
+                self.flags.set_nz(self.reg.x);
             },
             Mnemonic::INY => {
                 self.reg.y = self.reg.y.wrapping_add(1);
-                self.flags = Flags::from_value_nz(self.reg.y);
-                0
+                self.flags.set_nz(self.reg.y);
             },
             Mnemonic::JMP => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                self.reg.pc = addr;
-                page_cycles
+                self.reg.pc = self.get_address(mem, instruction.address_mode, Access::Read);
             },
             Mnemonic::JSR => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
+                // The high byte of the target is only fetched once the
+                // return address has been pushed, which is that of the
+                // last byte of the JSR, RTS adds one when popping it.
+                let lo = self.next(mem);
+                self.read_stack(mem);
                 let pc = self.reg.pc;
                 self.push_u16(mem, pc);
-                self.reg.pc = addr;
-                page_cycles
+                let hi = self.next(mem);
+                self.reg.pc = (lo as u16) | ((hi as u16) << 8);
             },
             Mnemonic::LDA => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 self.reg.a = value;
-                self.flags = Flags::from_value_nz(self.reg.a);
-                page_cycles
+                self.flags.set_nz(self.reg.a);
             },
             Mnemonic::LDX => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 self.reg.x = value;
-                self.flags = Flags::from_value_nz(self.reg.x);
-                page_cycles
+                self.flags.set_nz(self.reg.x);
             },
             Mnemonic::LDY => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
+                let value = self.get_address_value(mem, instruction.address_mode);
                 self.reg.y = value;
-                self.flags = Flags::from_value_nz(self.reg.y);
-                page_cycles
+                self.flags.set_nz(self.reg.y);
             },
             Mnemonic::LSR => {
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = (value as u16) >> 1;
-                    (value as u8, Flags::from_value_zc(value))
-                })
+                self.with_address_modify(mem, instruction.address_mode, lsr);
+            },
+            Mnemonic::NOP => {
+                // The unofficial NOPs with an operand still read it,
+                // including the extra cycle when crossing a page.
+                match instruction.address_mode {
+                    AddressMode::Implied => {},
+                    address_mode => {
+                        self.get_address_value(mem, address_mode);
+                    },
+                }
             },
-            Mnemonic::NOP => { 0 },
             Mnemonic::ORA => {
-                let (value, page_cycles) = self.get_address_value(mem, instruction.address_mode);
-                self.reg.a = self.reg.a | value;
-                self.flags = Flags::from_value_nz(self.reg.a);
-                page_cycles
+                let value = self.get_address_value(mem, instruction.address_mode);
+                self.reg.a |= value;
+                self.flags.set_nz(self.reg.a);
             },
             Mnemonic::PHA => {
                 let a = self.reg.a;
                 self.push(mem, a);
-                0
             },
             Mnemonic::PHP => {
                 self.php(mem);
-                0
             },
             Mnemonic::PLA => {
+                self.read_stack(mem);
                 self.reg.a = self.pop(mem);
-                0
+                self.flags.set_nz(self.reg.a);
             },
             Mnemonic::PLP => {
+                self.read_stack(mem);
                 self.flags = Flags::from_byte(self.pop(mem));
-                0
             },
             Mnemonic::ROL => {
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = (value as u16).rotate_left(1);
-                    ((value as u8).rotate_left(1), Flags::from_value_nzc(value))
-                })
+                self.with_address_modify(mem, instruction.address_mode, rol);
             },
             Mnemonic::ROR => {
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let value = (value as u16).rotate_right(1);
-                    ((value as u8).rotate_right(1), Flags::from_value_nzc(value))
-                })
+                self.with_address_modify(mem, instruction.address_mode, ror);
             },
             Mnemonic::RTI => {
+                self.read_stack(mem);
                 self.flags = Flags::from_byte(self.pop(mem));
                 self.reg.pc = self.pop_u16(mem);
-                0
             },
             Mnemonic::RTS => {
-                self.reg.pc = self.pop_u16(mem) + 1;
-                0
+                self.read_stack(mem);
+                let pc = self.pop_u16(mem);
+                // The PC is incremented past the JSR with a dummy read.
+                self.read(mem, pc);
+                self.reg.pc = pc.wrapping_add(1);
             },
             Mnemonic::SBC => {
-                let a = self.reg.a;
-                let c = self.flags.c as u8;
-                self.with_address_modify(mem, instruction.address_mode, |value| {
-                    let new = (a as u16)
-                        .wrapping_sub((value as u16))
-                        .wrapping_sub(c as u16);
-                    (a.wrapping_sub(value).wrapping_sub(c), Flags::from_value_nzcv(new))
-                })
+                let value = self.get_address_value(mem, instruction.address_mode);
+                self.sbc(value);
             },
             Mnemonic::SEC => {
                 self.flags.c = true;
-                0
             },
             Mnemonic::SED => {
                 self.flags.d = true;
-                0
             },
             Mnemonic::SEI => {
                 self.flags.i = true;
-                0
             },
             Mnemonic::STA => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                mem.cpu_write(addr, self.reg.a);
-                page_cycles
+                let a = self.reg.a;
+                self.store(mem, instruction.address_mode, a);
             },
             Mnemonic::STX => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                mem.cpu_write(addr, self.reg.x);
-                page_cycles
+                let x = self.reg.x;
+                self.store(mem, instruction.address_mode, x);
             },
             Mnemonic::STY => {
-                let (addr, page_cycles) = self.get_address(mem, instruction.address_mode);
-                mem.cpu_write(addr, self.reg.y);
-                page_cycles
+                let y = self.reg.y;
+                self.store(mem, instruction.address_mode, y);
             },
             Mnemonic::TAX => {
                 self.reg.x = self.reg.a;
-                0
+                self.flags.set_nz(self.reg.x);
             },
             Mnemonic::TAY => {
                 self.reg.y = self.reg.a;
-                0
+                self.flags.set_nz(self.reg.y);
             },
             Mnemonic::TSX => {
-                self.reg.x = self.reg.sp as u8;
-                0
+                self.reg.x = self.reg.sp;
+                self.flags.set_nz(self.reg.x);
             },
             Mnemonic::TXA => {
                 self.reg.a = self.reg.x;
-                0
+                self.flags.set_nz(self.reg.a);
             },
             Mnemonic::TXS => {
-                self.reg.sp = self.reg.x as u16;
-                0
+                // Unlike the other transfers TXS leaves the flags alone.
+                self.reg.sp = self.reg.x;
             },
             Mnemonic::TYA => {
                 self.reg.a = self.reg.y;
-                0
+                self.flags.set_nz(self.reg.a);
             },
-        };
-        instruction.cycles + page_cycles
+
+            // Unofficial opcodes: http://wiki.nesdev.com/w/index.php/CPU_unofficial_opcodes
+            Mnemonic::ALR => {
+                let value = self.next(mem);
+                self.reg.a = lsr(&mut self.flags, self.reg.a & value);
+            },
+            Mnemonic::ANC => {
+                let value = self.next(mem);
+                self.reg.a &= value;
+                self.flags.set_nz(self.reg.a);
+                self.flags.c = self.flags.n;
+            },
+            Mnemonic::ARR => {
+                let value = self.next(mem);
+                let result = ror(&mut self.flags, self.reg.a & value);
+                self.flags.c = (result & 0x40) != 0;
+                self.flags.v = (((result >> 6) ^ (result >> 5)) & 1) != 0;
+                self.reg.a = result;
+            },
+            Mnemonic::AXS => {
+                let value = self.next(mem);
+                let ax = self.reg.a & self.reg.x;
+                self.reg.x = ax.wrapping_sub(value);
+                self.flags.c = ax >= value;
+                self.flags.set_nz(self.reg.x);
+            },
+            Mnemonic::DCP => {
+                let value = self.with_address_modify(mem, instruction.address_mode, |_, value| {
+                    value.wrapping_sub(1)
+                });
+                let a = self.reg.a;
+                self.cmp(a, value);
+            },
+            Mnemonic::ISC => {
+                let value = self.with_address_modify(mem, instruction.address_mode, |_, value| {
+                    value.wrapping_add(1)
+                });
+                self.sbc(value);
+            },
+            Mnemonic::KIL => {
+                self.halted = Some(self.reg.pc.wrapping_sub(1));
+            },
+            Mnemonic::LAS => {
+                let value = self.get_address_value(mem, instruction.address_mode);
+                let result = value & self.reg.sp;
+                self.reg.a = result;
+                self.reg.x = result;
+                self.reg.sp = result;
+                self.flags.set_nz(result);
+            },
+            Mnemonic::LAX => {
+                let value = self.get_address_value(mem, instruction.address_mode);
+                self.reg.a = value;
+                self.reg.x = value;
+                self.flags.set_nz(value);
+            },
+            Mnemonic::LXA => {
+                // Unstable, the constant ORed into A varies between chips.
+                let value = self.next(mem);
+                let result = (self.reg.a | 0xee) & value;
+                self.reg.a = result;
+                self.reg.x = result;
+                self.flags.set_nz(result);
+            },
+            Mnemonic::RLA => {
+                let value = self.with_address_modify(mem, instruction.address_mode, rol);
+                self.reg.a &= value;
+                self.flags.set_nz(self.reg.a);
+            },
+            Mnemonic::RRA => {
+                let value = self.with_address_modify(mem, instruction.address_mode, ror);
+                self.adc(value);
+            },
+            Mnemonic::SAX => {
+                let value = self.reg.a & self.reg.x;
+                self.store(mem, instruction.address_mode, value);
+            },
+            Mnemonic::SHA => {
+                let value = self.reg.a & self.reg.x;
+                self.unstable_store(mem, instruction.address_mode, value);
+            },
+            Mnemonic::SHX => {
+                let value = self.reg.x;
+                self.unstable_store(mem, instruction.address_mode, value);
+            },
+            Mnemonic::SHY => {
+                let value = self.reg.y;
+                self.unstable_store(mem, instruction.address_mode, value);
+            },
+            Mnemonic::SLO => {
+                let value = self.with_address_modify(mem, instruction.address_mode, asl);
+                self.reg.a |= value;
+                self.flags.set_nz(self.reg.a);
+            },
+            Mnemonic::SRE => {
+                let value = self.with_address_modify(mem, instruction.address_mode, lsr);
+                self.reg.a ^= value;
+                self.flags.set_nz(self.reg.a);
+            },
+            Mnemonic::TAS => {
+                self.reg.sp = self.reg.a & self.reg.x;
+                let value = self.reg.sp;
+                self.unstable_store(mem, instruction.address_mode, value);
+            },
+            Mnemonic::XAA => {
+                // Unstable, the constant ORed into A varies between chips.
+                let value = self.next(mem);
+                self.reg.a = (self.reg.a | 0xee) & self.reg.x & value;
+                self.flags.set_nz(self.reg.a);
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::bus::Ram;
+
+    #[test]
+    fn test_x_indexed_indirect() {
+        let mut mem = Ram::new();
+        mem.write(0x02, 0x37);
+        mem.write(0x03, 0x13);
+
+        let mut cpu = Cpu::new();
+        cpu.reg.x = 0x01;
+        let addr = cpu.x_indexed_indirect(&mut mem, 0x01);
+        assert_eq!(addr, 0x1337);
+    }
+
+    #[test]
+    fn test_x_indexed_indirect_wraps_zero_page() {
+        let mut mem = Ram::new();
+        mem.write(0xff, 0x37);
+        mem.write(0x00, 0x13);
+
+        let mut cpu = Cpu::new();
+        cpu.reg.x = 0x0f;
+        let addr = cpu.x_indexed_indirect(&mut mem, 0xf0);
+        assert_eq!(addr, 0x1337);
+    }
+
+    #[test]
+    fn test_indirect_indexed() {
+        let mut mem = Ram::new();
+        mem.write(0x10, 0x30);
+        mem.write(0x11, 0x13);
+
+        let mut cpu = Cpu::new();
+        cpu.reg.y = 0x07;
+        assert_eq!(cpu.indirect_indexed(&mut mem, 0x10, Access::Read), 0x1337);
+        assert_eq!(cpu.cycles(), 2);
+
+        // Crossing a page costs a dummy read from the unfixed address.
+        cpu.reg.y = 0xf0;
+        assert_eq!(cpu.indirect_indexed(&mut mem, 0x10, Access::Read), 0x1420);
+        assert_eq!(cpu.cycles(), 5);
+
+        // As does every write, crossing or not.
+        cpu.reg.y = 0x07;
+        assert_eq!(cpu.indirect_indexed(&mut mem, 0x10, Access::Write), 0x1337);
+        assert_eq!(cpu.cycles(), 8);
+    }
+
+    /// A RAM bus with the reset vector pointing at 0x8000 and
+    /// the NMI and IRQ vectors at 0x9000 and 0xa000 respectively,
+    /// each of which starts with a couple of NOPs.
+    fn ram_with_vectors() -> Ram {
+        let mut mem = Ram::new();
+        mem.load(0xfffa, &[0x00, 0x90, 0x00, 0x80, 0x00, 0xa0]);
+        for &addr in &[0x8000, 0x9000, 0xa000] {
+            mem.load(addr, &[0xea, 0xea]);
+        }
+        mem
+    }
+
+    #[test]
+    fn test_nmi_is_edge_triggered() {
+        let mut mem = ram_with_vectors();
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+
+        cpu.set_nmi(true);
+        assert_eq!(cpu.step(&mut mem), 7);
+        assert_eq!(cpu.reg.pc, 0x9000);
+        assert!(cpu.flags.i);
+
+        // Holding the line high does not retrigger.
+        cpu.set_nmi(true);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.reg.pc, 0x9001);
+
+        cpu.set_nmi(false);
+        cpu.set_nmi(true);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.reg.pc, 0x9000);
+    }
+
+    #[test]
+    fn test_irq_is_masked_by_i_flag() {
+        let mut mem = ram_with_vectors();
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        cpu.set_irq(true);
+
+        cpu.flags.i = true;
+        cpu.step(&mut mem);
+        assert_eq!(cpu.reg.pc, 0x8001);
+
+        cpu.flags.i = false;
+        assert_eq!(cpu.step(&mut mem), 7);
+        assert_eq!(cpu.reg.pc, 0xa000);
+        assert_eq!(cpu.reg.sp, 0xfd - 3);
+        assert_eq!(mem.read(0x01fd), 0x80);
+        assert_eq!(mem.read(0x01fc), 0x01);
+    }
+
+    #[test]
+    fn test_pushed_b_flag() {
+        let mut mem = ram_with_vectors();
+        // BRK
+        mem.write(0x8000, 0x00);
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+
+        cpu.set_nmi(true);
+        cpu.step(&mut mem);
+        assert_eq!(mem.read(0x01fb) & 0x30, 0x20);
+
+        cpu.reset(&mut mem);
+        cpu.step(&mut mem);
+        assert_eq!(cpu.reg.pc, 0xa000);
+        assert_eq!(mem.read(0x01fb) & 0x30, 0x30);
+        // The return address skips BRK's padding byte.
+        assert_eq!(mem.read(0x01fc), 0x02);
+    }
+
+    #[test]
+    fn test_step_on_ram() {
+        let mut mem = Ram::new();
+        mem.write(0xfffc, 0x00);
+        mem.write(0xfffd, 0x80);
+        // LDX #$42; STX $0200
+        mem.load(0x8000, &[0xa2, 0x42, 0x8e, 0x00, 0x02]);
+
+        let mut cpu = Cpu::new();
+        cpu.reset(&mut mem);
+        assert_eq!(cpu.reg.pc, 0x8000);
+        assert_eq!(cpu.step(&mut mem), 2);
+        assert_eq!(cpu.step(&mut mem), 4);
+        assert_eq!(mem.read(0x0200), 0x42);
+        assert_eq!(cpu.reg.pc, 0x8005);
     }
 }
 
-// impl Memory for Vec<u8> {
-//     fn read(&self, addr: u16) -> u8 {
-//         self[addr as usize]
-//     }
-//     fn write(&mut self, addr: u16, x: u8) {
-//         self.data[addr as usize] = x;
-//     }
-// }
-
-// #[test]
-// fn test_indexed_indirect_x() {
-//     let mut mem = Vec::new();
-//     mem.resize(0x2000, 0);
-//     mem[0x02] = 0x37;
-//     mem[0x03] = 0x13;
-
-//     // Actual data located at 0x1337
-//     mem[0x1337] = 0xfe
-
-//     let mut cpu = Cpu::new();
-//     cpu.reg.x = 0x01;
-//     let addr = cpu.indexed_indirect_x(0x10);
-//     assert_eq!(addr, 0x1f);
-// }
-
-// #[test]
-// fn test_indirect_indexed_y() {
-//     let mut cpu = Cpu::new();
-//     cpu.reg.y = 0x0f;
-//     let addr = cpu.indirect_indexed_y(0x10);
-
-// }
\ No newline at end of file
@@ -33,6 +33,18 @@ pub enum Mnemonic {
     TAX, TAY,
     TSX, TXA,
     TXS, TYA,
+
+    // Unofficial mnemonics
+    ALR, ANC,
+    ARR, AXS,
+    DCP, ISC,
+    KIL, LAS,
+    LAX, LXA,
+    RLA, RRA,
+    SAX, SHA,
+    SHX, SHY,
+    SLO, SRE,
+    TAS, XAA,
 }
 
 #[derive(Clone, Copy, Debug, Eq, PartialEq)]
@@ -65,163 +77,289 @@ impl Instruction {
         let (mnemonic, address_mode, cycles) = match code {
             0x00 => (BRK, Implied, 7),
             0x01 => (ORA, XIndexedIndirect, 6),
+            0x02 => (KIL, Implied, 2), // Unofficial
+            0x03 => (SLO, XIndexedIndirect, 8), // Unofficial
+            0x04 => (NOP, ZeroPage, 3), // Unofficial
             0x05 => (ORA, ZeroPage, 3),
             0x06 => (ASL, ZeroPage, 5),
+            0x07 => (SLO, ZeroPage, 5), // Unofficial
             0x08 => (PHP, Implied, 3),
             0x09 => (ORA, Immediate, 2),
             0x0a => (ASL, Accumulator, 2),
+            0x0b => (ANC, Immediate, 2), // Unofficial
+            0x0c => (NOP, Absolute, 4), // Unofficial
             0x0d => (ORA, Absolute, 4),
             0x0e => (ASL, Absolute, 6),
+            0x0f => (SLO, Absolute, 6), // Unofficial
             0x10 => (BPL, Relative, 2),
             0x11 => (ORA, IndirectYIndexed, 5),
+            0x12 => (KIL, Implied, 2), // Unofficial
+            0x13 => (SLO, IndirectYIndexed, 8), // Unofficial
+            0x14 => (NOP, ZeroPageXIndexed, 4), // Unofficial
             0x15 => (ORA, ZeroPageXIndexed, 4),
             0x16 => (ASL, ZeroPageXIndexed, 6),
+            0x17 => (SLO, ZeroPageXIndexed, 6), // Unofficial
             0x18 => (CLC, Implied, 2),
             0x19 => (ORA, AbsoluteYIndexed, 4),
+            0x1a => (NOP, Implied, 2), // Unofficial
+            0x1b => (SLO, AbsoluteYIndexed, 7), // Unofficial
+            0x1c => (NOP, AbsoluteXIndexed, 4), // Unofficial
             0x1d => (ORA, AbsoluteXIndexed, 4),
             0x1e => (ASL, AbsoluteXIndexed, 7),
+            0x1f => (SLO, AbsoluteXIndexed, 7), // Unofficial
             0x20 => (JSR, Absolute, 6),
             0x21 => (AND, XIndexedIndirect, 6),
+            0x22 => (KIL, Implied, 2), // Unofficial
+            0x23 => (RLA, XIndexedIndirect, 8), // Unofficial
             0x24 => (BIT, ZeroPage, 3),
             0x25 => (AND, ZeroPage, 3),
             0x26 => (ROL, ZeroPage, 5),
+            0x27 => (RLA, ZeroPage, 5), // Unofficial
             0x28 => (PLP, Implied, 4),
             0x29 => (AND, Immediate, 2),
             0x2a => (ROL, Accumulator, 2),
+            0x2b => (ANC, Immediate, 2), // Unofficial
             0x2c => (BIT, Absolute, 4),
             0x2d => (AND, Absolute, 4),
             0x2e => (ROL, Absolute, 6),
+            0x2f => (RLA, Absolute, 6), // Unofficial
             0x30 => (BMI, Relative, 2),
             0x31 => (AND, IndirectYIndexed, 5),
+            0x32 => (KIL, Implied, 2), // Unofficial
+            0x33 => (RLA, IndirectYIndexed, 8), // Unofficial
+            0x34 => (NOP, ZeroPageXIndexed, 4), // Unofficial
             0x35 => (AND, ZeroPageXIndexed, 4),
             0x36 => (ROL, ZeroPageXIndexed, 6),
+            0x37 => (RLA, ZeroPageXIndexed, 6), // Unofficial
             0x38 => (SEC, Implied, 2),
             0x39 => (AND, AbsoluteYIndexed, 4),
+            0x3a => (NOP, Implied, 2), // Unofficial
+            0x3b => (RLA, AbsoluteYIndexed, 7), // Unofficial
+            0x3c => (NOP, AbsoluteXIndexed, 4), // Unofficial
             0x3d => (AND, AbsoluteXIndexed, 4),
             0x3e => (ROL, AbsoluteXIndexed, 7),
+            0x3f => (RLA, AbsoluteXIndexed, 7), // Unofficial
             0x40 => (RTI, Implied, 6),
             0x41 => (EOR, XIndexedIndirect, 6),
+            0x42 => (KIL, Implied, 2), // Unofficial
+            0x43 => (SRE, XIndexedIndirect, 8), // Unofficial
+            0x44 => (NOP, ZeroPage, 3), // Unofficial
             0x45 => (EOR, ZeroPage, 3),
             0x46 => (LSR, ZeroPage, 5),
+            0x47 => (SRE, ZeroPage, 5), // Unofficial
             0x48 => (PHA, Implied, 3),
             0x49 => (EOR, Immediate, 2),
             0x4a => (LSR, Accumulator, 2),
+            0x4b => (ALR, Immediate, 2), // Unofficial
             0x4c => (JMP, Absolute, 3),
             0x4d => (EOR, Absolute, 4),
             0x4e => (LSR, Absolute, 6),
+            0x4f => (SRE, Absolute, 6), // Unofficial
             0x50 => (BVC, Relative, 2),
             0x51 => (EOR, IndirectYIndexed, 5),
+            0x52 => (KIL, Implied, 2), // Unofficial
+            0x53 => (SRE, IndirectYIndexed, 8), // Unofficial
+            0x54 => (NOP, ZeroPageXIndexed, 4), // Unofficial
             0x55 => (EOR, ZeroPageXIndexed, 4),
             0x56 => (LSR, ZeroPageXIndexed, 6),
+            0x57 => (SRE, ZeroPageXIndexed, 6), // Unofficial
             0x58 => (CLI, Implied, 2),
             0x59 => (EOR, AbsoluteYIndexed, 4),
+            0x5a => (NOP, Implied, 2), // Unofficial
+            0x5b => (SRE, AbsoluteYIndexed, 7), // Unofficial
+            0x5c => (NOP, AbsoluteXIndexed, 4), // Unofficial
             0x5d => (EOR, AbsoluteXIndexed, 4),
             0x5e => (LSR, AbsoluteXIndexed, 7),
+            0x5f => (SRE, AbsoluteXIndexed, 7), // Unofficial
             0x60 => (RTS, Implied, 6),
             0x61 => (ADC, XIndexedIndirect, 6),
+            0x62 => (KIL, Implied, 2), // Unofficial
+            0x63 => (RRA, XIndexedIndirect, 8), // Unofficial
+            0x64 => (NOP, ZeroPage, 3), // Unofficial
             0x65 => (ADC, ZeroPage, 3),
             0x66 => (ROR, ZeroPage, 5),
+            0x67 => (RRA, ZeroPage, 5), // Unofficial
             0x68 => (PLA, Implied, 4),
             0x69 => (ADC, Immediate, 2),
             0x6a => (ROR, Accumulator, 2),
+            0x6b => (ARR, Immediate, 2), // Unofficial
             0x6c => (JMP, Indirect, 5),
             0x6d => (ADC, Absolute, 4),
             0x6e => (ROR, Absolute, 6),
+            0x6f => (RRA, Absolute, 6), // Unofficial
             0x70 => (BVS, Relative, 2),
             0x71 => (ADC, IndirectYIndexed, 5),
+            0x72 => (KIL, Implied, 2), // Unofficial
+            0x73 => (RRA, IndirectYIndexed, 8), // Unofficial
+            0x74 => (NOP, ZeroPageXIndexed, 4), // Unofficial
             0x75 => (ADC, ZeroPageXIndexed, 4),
             0x76 => (ROR, ZeroPageXIndexed, 6),
+            0x77 => (RRA, ZeroPageXIndexed, 6), // Unofficial
             0x78 => (SEI, Implied, 2),
             0x79 => (ADC, AbsoluteYIndexed, 4),
+            0x7a => (NOP, Implied, 2), // Unofficial
+            0x7b => (RRA, AbsoluteYIndexed, 7), // Unofficial
+            0x7c => (NOP, AbsoluteXIndexed, 4), // Unofficial
             0x7d => (ADC, AbsoluteXIndexed, 4),
             0x7e => (ROR, AbsoluteXIndexed, 7),
+            0x7f => (RRA, AbsoluteXIndexed, 7), // Unofficial
+            0x80 => (NOP, Immediate, 2), // Unofficial
             0x81 => (STA, XIndexedIndirect, 6),
+            0x82 => (NOP, Immediate, 2), // Unofficial
+            0x83 => (SAX, XIndexedIndirect, 6), // Unofficial
             0x84 => (STY, ZeroPage, 3),
             0x85 => (STA, ZeroPage, 3),
             0x86 => (STX, ZeroPage, 3),
+            0x87 => (SAX, ZeroPage, 3), // Unofficial
             0x88 => (DEY, Implied, 2),
+            0x89 => (NOP, Immediate, 2), // Unofficial
             0x8a => (TXA, Implied, 2),
+            0x8b => (XAA, Immediate, 2), // Unofficial
             0x8c => (STY, Absolute, 4),
             0x8d => (STA, Absolute, 4),
             0x8e => (STX, Absolute, 4),
+            0x8f => (SAX, Absolute, 4), // Unofficial
             0x90 => (BCC, Relative, 2),
             0x91 => (STA, IndirectYIndexed, 6),
+            0x92 => (KIL, Implied, 2), // Unofficial
+            0x93 => (SHA, IndirectYIndexed, 6), // Unofficial
             0x94 => (STY, ZeroPageXIndexed, 4),
             0x95 => (STA, ZeroPageXIndexed, 4),
             0x96 => (STX, ZeroPageYIndexed, 4),
+            0x97 => (SAX, ZeroPageYIndexed, 4), // Unofficial
             0x98 => (TYA, Implied, 2),
             0x99 => (STA, AbsoluteYIndexed, 5),
             0x9a => (TXS, Implied, 2),
+            0x9b => (TAS, AbsoluteYIndexed, 5), // Unofficial
+            0x9c => (SHY, AbsoluteXIndexed, 5), // Unofficial
             0x9d => (STA, AbsoluteXIndexed, 5),
+            0x9e => (SHX, AbsoluteYIndexed, 5), // Unofficial
+            0x9f => (SHA, AbsoluteYIndexed, 5), // Unofficial
             0xa0 => (LDY, Immediate, 2),
             0xa1 => (LDA, XIndexedIndirect, 6),
             0xa2 => (LDX, Immediate, 2),
+            0xa3 => (LAX, XIndexedIndirect, 6), // Unofficial
             0xa4 => (LDY, ZeroPage, 3),
             0xa5 => (LDA, ZeroPage, 3),
             0xa6 => (LDX, ZeroPage, 3),
+            0xa7 => (LAX, ZeroPage, 3), // Unofficial
             0xa8 => (TAY, Implied, 2),
             0xa9 => (LDA, Immediate, 2),
             0xaa => (TAX, Implied, 2),
+            0xab => (LXA, Immediate, 2), // Unofficial
             0xac => (LDY, Absolute, 4),
             0xad => (LDA, Absolute, 4),
             0xae => (LDX, Absolute, 4),
+            0xaf => (LAX, Absolute, 4), // Unofficial
             0xb0 => (BCS, Relative, 2),
             0xb1 => (LDA, IndirectYIndexed, 5),
+            0xb2 => (KIL, Implied, 2), // Unofficial
+            0xb3 => (LAX, IndirectYIndexed, 5), // Unofficial
             0xb4 => (LDY, ZeroPageXIndexed, 4),
             0xb5 => (LDA, ZeroPageXIndexed, 4),
             0xb6 => (LDX, ZeroPageYIndexed, 4),
+            0xb7 => (LAX, ZeroPageYIndexed, 4), // Unofficial
             0xb8 => (CLV, Implied, 2),
             0xb9 => (LDA, AbsoluteYIndexed, 4),
             0xba => (TSX, Implied, 2),
+            0xbb => (LAS, AbsoluteYIndexed, 4), // Unofficial
             0xbc => (LDY, AbsoluteXIndexed, 4),
             0xbd => (LDA, AbsoluteXIndexed, 4),
             0xbe => (LDX, AbsoluteYIndexed, 4),
+            0xbf => (LAX, AbsoluteYIndexed, 4), // Unofficial
             0xc0 => (CPY, Immediate, 2),
             0xc1 => (CMP, XIndexedIndirect, 6),
+            0xc2 => (NOP, Immediate, 2), // Unofficial
+            0xc3 => (DCP, XIndexedIndirect, 8), // Unofficial
             0xc4 => (CPY, ZeroPage, 3),
             0xc5 => (CMP, ZeroPage, 3),
             0xc6 => (DEC, ZeroPage, 5),
+            0xc7 => (DCP, ZeroPage, 5), // Unofficial
             0xc8 => (INY, Implied, 2),
             0xc9 => (CMP, Immediate, 2),
             0xca => (DEX, Implied, 2),
+            0xcb => (AXS, Immediate, 2), // Unofficial
             0xcc => (CPY, Absolute, 4),
             0xcd => (CMP, Absolute, 4),
-            0xce => (DEC, Absolute, 3),
+            0xce => (DEC, Absolute, 6),
+            0xcf => (DCP, Absolute, 6), // Unofficial
             0xd0 => (BNE, Relative, 2),
             0xd1 => (CMP, IndirectYIndexed, 5),
+            0xd2 => (KIL, Implied, 2), // Unofficial
+            0xd3 => (DCP, IndirectYIndexed, 8), // Unofficial
+            0xd4 => (NOP, ZeroPageXIndexed, 4), // Unofficial
             0xd5 => (CMP, ZeroPageXIndexed, 4),
             0xd6 => (DEC, ZeroPageXIndexed, 6),
+            0xd7 => (DCP, ZeroPageXIndexed, 6), // Unofficial
             0xd8 => (CLD, Implied, 2),
             0xd9 => (CMP, AbsoluteYIndexed, 4),
+            0xda => (NOP, Implied, 2), // Unofficial
+            0xdb => (DCP, AbsoluteYIndexed, 7), // Unofficial
+            0xdc => (NOP, AbsoluteXIndexed, 4), // Unofficial
             0xdd => (CMP, AbsoluteXIndexed, 4),
             0xde => (DEC, AbsoluteXIndexed, 7),
+            0xdf => (DCP, AbsoluteXIndexed, 7), // Unofficial
             0xe0 => (CPX, Immediate, 2),
             0xe1 => (SBC, XIndexedIndirect, 6),
+            0xe2 => (NOP, Immediate, 2), // Unofficial
+            0xe3 => (ISC, XIndexedIndirect, 8), // Unofficial
             0xe4 => (CPX, ZeroPage, 3),
             0xe5 => (SBC, ZeroPage, 3),
             0xe6 => (INC, ZeroPage, 5),
+            0xe7 => (ISC, ZeroPage, 5), // Unofficial
             0xe8 => (INX, Implied, 2),
             0xe9 => (SBC, Immediate, 2),
             0xea => (NOP, Implied, 2),
+            0xeb => (SBC, Immediate, 2), // Unofficial
             0xec => (CPX, Absolute, 4),
             0xed => (SBC, Absolute, 4),
             0xee => (INC, Absolute, 6),
+            0xef => (ISC, Absolute, 6), // Unofficial
             0xf0 => (BEQ, Relative, 2),
             0xf1 => (SBC, IndirectYIndexed, 5),
+            0xf2 => (KIL, Implied, 2), // Unofficial
+            0xf3 => (ISC, IndirectYIndexed, 8), // Unofficial
+            0xf4 => (NOP, ZeroPageXIndexed, 4), // Unofficial
             0xf5 => (SBC, ZeroPageXIndexed, 4),
             0xf6 => (INC, ZeroPageXIndexed, 6),
+            0xf7 => (ISC, ZeroPageXIndexed, 6), // Unofficial
             0xf8 => (SED, Implied, 2),
             0xf9 => (SBC, AbsoluteYIndexed, 4),
+            0xfa => (NOP, Implied, 2), // Unofficial
+            0xfb => (ISC, AbsoluteYIndexed, 7), // Unofficial
+            0xfc => (NOP, AbsoluteXIndexed, 4), // Unofficial
             0xfd => (SBC, AbsoluteXIndexed, 4),
             0xfe => (INC, AbsoluteXIndexed, 7),
-            _ => panic!("Unrecognised op code: {:02x}", code),
+            0xff => (ISC, AbsoluteXIndexed, 7), // Unofficial
         };
 
         Instruction {
-            code: code,
-            mnemonic: mnemonic,
-            address_mode: address_mode,
-            cycles: cycles,
+            code,
+            mnemonic,
+            address_mode,
+            cycles,
+        }
+    }
+
+    /// The length of the instruction in bytes, including the opcode.
+    pub fn size(&self) -> u16 {
+        match self.address_mode {
+            Accumulator | Implied => 1,
+            Absolute | AbsoluteXIndexed | AbsoluteYIndexed | Indirect => 3,
+            Immediate | XIndexedIndirect | IndirectYIndexed | Relative |
+            ZeroPage | ZeroPageXIndexed | ZeroPageYIndexed => 2,
+        }
+    }
+
+    /// Whether this is one of the 151 documented opcodes
+    /// as opposed to an undocumented/illegal one.
+    pub fn is_official(&self) -> bool {
+        match self.mnemonic {
+            ALR | ANC | ARR | AXS | DCP | ISC | KIL | LAS | LAX | LXA |
+            RLA | RRA | SAX | SHA | SHX | SHY | SLO | SRE | TAS | XAA => false,
+            NOP => self.code == 0xea,
+            SBC => self.code != 0xeb,
+            _ => true,
         }
     }
 }
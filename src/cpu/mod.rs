@@ -1,5 +1,9 @@
+mod bus;
 mod cpu;
 mod instruction;
+#[cfg(test)]
+mod tests;
 
-pub use self::cpu::Cpu;
-pub use self::instruction::{AddressMode, Instruction, Mnemonic};
\ No newline at end of file
+pub use self::bus::{Bus, Ram};
+pub use self::cpu::{Cpu, Flags, Registers};
+pub use self::instruction::{AddressMode, Instruction, Mnemonic};
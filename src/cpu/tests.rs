@@ -0,0 +1,863 @@
+//! Per-opcode tests for the official 6502 instruction set,
+//! run against a flat RAM bus.
+
+use super::{Bus, Cpu, Flags, Instruction, Ram};
+
+const ORIGIN: u16 = 0x0600;
+
+/// Base cycle counts of every official opcode, not including
+/// the extra cycles for taken branches or crossing a page.
+const OFFICIAL_CYCLES: &[(u8, isize)] = &[
+    // ADC
+    (0x69, 2), (0x65, 3), (0x75, 4), (0x6d, 4), (0x7d, 4), (0x79, 4), (0x61, 6), (0x71, 5),
+    // AND
+    (0x29, 2), (0x25, 3), (0x35, 4), (0x2d, 4), (0x3d, 4), (0x39, 4), (0x21, 6), (0x31, 5),
+    // ASL
+    (0x0a, 2), (0x06, 5), (0x16, 6), (0x0e, 6), (0x1e, 7),
+    // Branches
+    (0x90, 2), (0xb0, 2), (0xf0, 2), (0x30, 2), (0xd0, 2), (0x10, 2), (0x50, 2), (0x70, 2),
+    // BIT
+    (0x24, 3), (0x2c, 4),
+    // BRK
+    (0x00, 7),
+    // Flag instructions
+    (0x18, 2), (0xd8, 2), (0x58, 2), (0xb8, 2), (0x38, 2), (0xf8, 2), (0x78, 2),
+    // CMP
+    (0xc9, 2), (0xc5, 3), (0xd5, 4), (0xcd, 4), (0xdd, 4), (0xd9, 4), (0xc1, 6), (0xd1, 5),
+    // CPX, CPY
+    (0xe0, 2), (0xe4, 3), (0xec, 4), (0xc0, 2), (0xc4, 3), (0xcc, 4),
+    // DEC, DEX, DEY
+    (0xc6, 5), (0xd6, 6), (0xce, 6), (0xde, 7), (0xca, 2), (0x88, 2),
+    // EOR
+    (0x49, 2), (0x45, 3), (0x55, 4), (0x4d, 4), (0x5d, 4), (0x59, 4), (0x41, 6), (0x51, 5),
+    // INC, INX, INY
+    (0xe6, 5), (0xf6, 6), (0xee, 6), (0xfe, 7), (0xe8, 2), (0xc8, 2),
+    // JMP, JSR
+    (0x4c, 3), (0x6c, 5), (0x20, 6),
+    // LDA
+    (0xa9, 2), (0xa5, 3), (0xb5, 4), (0xad, 4), (0xbd, 4), (0xb9, 4), (0xa1, 6), (0xb1, 5),
+    // LDX
+    (0xa2, 2), (0xa6, 3), (0xb6, 4), (0xae, 4), (0xbe, 4),
+    // LDY
+    (0xa0, 2), (0xa4, 3), (0xb4, 4), (0xac, 4), (0xbc, 4),
+    // LSR
+    (0x4a, 2), (0x46, 5), (0x56, 6), (0x4e, 6), (0x5e, 7),
+    // NOP
+    (0xea, 2),
+    // ORA
+    (0x09, 2), (0x05, 3), (0x15, 4), (0x0d, 4), (0x1d, 4), (0x19, 4), (0x01, 6), (0x11, 5),
+    // Stack
+    (0x48, 3), (0x08, 3), (0x68, 4), (0x28, 4),
+    // ROL
+    (0x2a, 2), (0x26, 5), (0x36, 6), (0x2e, 6), (0x3e, 7),
+    // ROR
+    (0x6a, 2), (0x66, 5), (0x76, 6), (0x6e, 6), (0x7e, 7),
+    // RTI, RTS
+    (0x40, 6), (0x60, 6),
+    // SBC
+    (0xe9, 2), (0xe5, 3), (0xf5, 4), (0xed, 4), (0xfd, 4), (0xf9, 4), (0xe1, 6), (0xf1, 5),
+    // STA
+    (0x85, 3), (0x95, 4), (0x8d, 4), (0x9d, 5), (0x99, 5), (0x81, 6), (0x91, 6),
+    // STX, STY
+    (0x86, 3), (0x96, 4), (0x8e, 4), (0x84, 3), (0x94, 4), (0x8c, 4),
+    // Transfers
+    (0xaa, 2), (0xa8, 2), (0xba, 2), (0x8a, 2), (0x9a, 2), (0x98, 2),
+];
+
+/// Load `program` at `ORIGIN`, point the reset vector at it and reset.
+fn setup(program: &[u8]) -> (Cpu, Ram) {
+    let mut mem = Ram::new();
+    mem.load(0xfffc, &[ORIGIN as u8, (ORIGIN >> 8) as u8]);
+    mem.load(ORIGIN, program);
+    let mut cpu = Cpu::new();
+    cpu.reset(&mut mem);
+    (cpu, mem)
+}
+
+/// Run `program` until the PC passes its end, returning the total cycles.
+fn run(cpu: &mut Cpu, mem: &mut Ram, len: usize) -> isize {
+    let mut cycles = 0;
+    while cpu.reg.pc < ORIGIN + len as u16 {
+        cycles += cpu.step(mem);
+    }
+    cycles
+}
+
+#[test]
+fn test_official_cycle_table() {
+    assert_eq!(OFFICIAL_CYCLES.len(), 151);
+    for &(code, cycles) in OFFICIAL_CYCLES {
+        assert_eq!(Instruction::from_code(code).cycles, cycles, "opcode {:02x}", code);
+    }
+}
+
+#[test]
+fn test_official_cycles_without_page_crossing() {
+    for &(code, cycles) in OFFICIAL_CYCLES {
+        // Operands of zero with zeroed index registers never cross
+        // a page, and with Z set and the other flags clear only
+        // branches that test C, N or V are taken.
+        let (mut cpu, mut mem) = setup(&[code, 0x00, 0x00]);
+        cpu.flags.z = true;
+        let taken = match code {
+            0x90 | 0x10 | 0x50 | 0xf0 => 1,
+            _ => 0,
+        };
+        assert_eq!(cpu.step(&mut mem), cycles + taken, "opcode {:02x}", code);
+    }
+}
+
+#[test]
+fn test_adc() {
+    // CLC; LDA #$50; ADC #$10
+    let (mut cpu, mut mem) = setup(&[0x18, 0xa9, 0x50, 0x69, 0x10]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x60);
+    assert!(!cpu.flags.c && !cpu.flags.v && !cpu.flags.n && !cpu.flags.z);
+
+    // Carry in is added.
+    // SEC; LDA #$01; ADC #$01
+    let (mut cpu, mut mem) = setup(&[0x38, 0xa9, 0x01, 0x69, 0x01]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x03);
+}
+
+#[test]
+fn test_adc_overflow_and_carry() {
+    // Positive + positive = negative sets V.
+    // CLC; LDA #$50; ADC #$50
+    let (mut cpu, mut mem) = setup(&[0x18, 0xa9, 0x50, 0x69, 0x50]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0xa0);
+    assert!(cpu.flags.v && cpu.flags.n && !cpu.flags.c);
+
+    // Negative + negative = positive sets V and C.
+    // CLC; LDA #$d0; ADC #$90
+    let (mut cpu, mut mem) = setup(&[0x18, 0xa9, 0xd0, 0x69, 0x90]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x60);
+    assert!(cpu.flags.v && cpu.flags.c && !cpu.flags.n);
+
+    // Unsigned overflow to zero sets C and Z but not V.
+    // CLC; LDA #$ff; ADC #$01
+    let (mut cpu, mut mem) = setup(&[0x18, 0xa9, 0xff, 0x69, 0x01]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.flags.c && cpu.flags.z && !cpu.flags.v);
+}
+
+#[test]
+fn test_adc_ignores_decimal_mode() {
+    // SED; CLC; LDA #$09; ADC #$01
+    let (mut cpu, mut mem) = setup(&[0xf8, 0x18, 0xa9, 0x09, 0x69, 0x01]);
+    run(&mut cpu, &mut mem, 6);
+    assert_eq!(cpu.reg.a, 0x0a);
+}
+
+#[test]
+fn test_sbc() {
+    // SEC; LDA #$50; SBC #$f0
+    let (mut cpu, mut mem) = setup(&[0x38, 0xa9, 0x50, 0xe9, 0xf0]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x60);
+    assert!(!cpu.flags.c && !cpu.flags.v);
+
+    // Positive - negative = negative sets V.
+    // SEC; LDA #$50; SBC #$b0
+    let (mut cpu, mut mem) = setup(&[0x38, 0xa9, 0x50, 0xe9, 0xb0]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0xa0);
+    assert!(cpu.flags.v && cpu.flags.n && !cpu.flags.c);
+
+    // A clear carry borrows one.
+    // CLC; LDA #$05; SBC #$02
+    let (mut cpu, mut mem) = setup(&[0x18, 0xa9, 0x05, 0xe9, 0x02]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x02);
+    assert!(cpu.flags.c);
+}
+
+#[test]
+fn test_logical_operations() {
+    // LDA #$f0; AND #$3c
+    let (mut cpu, mut mem) = setup(&[0xa9, 0xf0, 0x29, 0x3c]);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(cpu.reg.a, 0x30);
+
+    // LDA #$f0; ORA #$0f
+    let (mut cpu, mut mem) = setup(&[0xa9, 0xf0, 0x09, 0x0f]);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(cpu.reg.a, 0xff);
+    assert!(cpu.flags.n);
+
+    // LDA #$ff; EOR #$ff
+    let (mut cpu, mut mem) = setup(&[0xa9, 0xff, 0x49, 0xff]);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.flags.z);
+}
+
+#[test]
+fn test_logical_operations_do_not_write_memory() {
+    // LDA #$0f; AND $10
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x0f, 0x25, 0x10]);
+    mem.write(0x10, 0x3c);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(cpu.reg.a, 0x0c);
+    assert_eq!(mem.read(0x10), 0x3c);
+}
+
+#[test]
+fn test_loads_preserve_other_flags() {
+    // SEC; SEI; LDA #$00
+    let (mut cpu, mut mem) = setup(&[0x38, 0x78, 0xa9, 0x00]);
+    run(&mut cpu, &mut mem, 4);
+    assert!(cpu.flags.z && cpu.flags.c && cpu.flags.i);
+}
+
+#[test]
+fn test_asl_and_lsr() {
+    // LDA #$81; ASL A
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x81, 0x0a]);
+    run(&mut cpu, &mut mem, 3);
+    assert_eq!(cpu.reg.a, 0x02);
+    assert!(cpu.flags.c && !cpu.flags.n);
+
+    // LSR $10
+    let (mut cpu, mut mem) = setup(&[0x46, 0x10]);
+    mem.write(0x10, 0x81);
+    run(&mut cpu, &mut mem, 2);
+    assert_eq!(mem.read(0x10), 0x40);
+    assert!(cpu.flags.c && !cpu.flags.n && !cpu.flags.z);
+}
+
+#[test]
+fn test_rotates_use_carry_in() {
+    // SEC; LDA #$40; ROL A
+    let (mut cpu, mut mem) = setup(&[0x38, 0xa9, 0x40, 0x2a]);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(cpu.reg.a, 0x81);
+    assert!(!cpu.flags.c && cpu.flags.n);
+
+    // SEC; ROR $10
+    let (mut cpu, mut mem) = setup(&[0x38, 0x66, 0x10]);
+    mem.write(0x10, 0x01);
+    run(&mut cpu, &mut mem, 3);
+    assert_eq!(mem.read(0x10), 0x80);
+    assert!(cpu.flags.c && cpu.flags.n);
+
+    // CLC; ROR A with A = 1 leaves zero.
+    let (mut cpu, mut mem) = setup(&[0x18, 0x6a]);
+    cpu.reg.a = 0x01;
+    run(&mut cpu, &mut mem, 2);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.flags.c && cpu.flags.z);
+}
+
+#[test]
+fn test_inc_and_dec_memory() {
+    // INC $10; DEC $11
+    let (mut cpu, mut mem) = setup(&[0xe6, 0x10, 0xc6, 0x11]);
+    mem.write(0x10, 0xff);
+    mem.write(0x11, 0x00);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(mem.read(0x10), 0x00);
+    assert_eq!(mem.read(0x11), 0xff);
+    assert!(cpu.flags.n && !cpu.flags.z);
+}
+
+#[test]
+fn test_bit() {
+    // LDA #$01; BIT $10
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x01, 0x24, 0x10]);
+    mem.write(0x10, 0xc0);
+    run(&mut cpu, &mut mem, 4);
+    assert!(cpu.flags.z && cpu.flags.n && cpu.flags.v);
+
+    // LDA #$41; BIT $10
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x41, 0x24, 0x10]);
+    mem.write(0x10, 0x01);
+    run(&mut cpu, &mut mem, 4);
+    assert!(!cpu.flags.z && !cpu.flags.n && !cpu.flags.v);
+    assert_eq!(cpu.reg.a, 0x41);
+}
+
+#[test]
+fn test_compare() {
+    // LDA #$40; CMP #$40
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x40, 0xc9, 0x40]);
+    run(&mut cpu, &mut mem, 4);
+    assert!(cpu.flags.z && cpu.flags.c && !cpu.flags.n);
+
+    // LDX #$10; CPX #$20
+    let (mut cpu, mut mem) = setup(&[0xa2, 0x10, 0xe0, 0x20]);
+    run(&mut cpu, &mut mem, 4);
+    assert!(!cpu.flags.z && !cpu.flags.c && cpu.flags.n);
+
+    // LDY #$80; CPY #$01
+    let (mut cpu, mut mem) = setup(&[0xa0, 0x80, 0xc0, 0x01]);
+    run(&mut cpu, &mut mem, 4);
+    assert!(!cpu.flags.z && cpu.flags.c && !cpu.flags.n);
+}
+
+#[test]
+fn test_transfers_set_flags() {
+    // LDA #$80; TAX; TAY
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x80, 0xaa, 0xa8]);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!((cpu.reg.x, cpu.reg.y), (0x80, 0x80));
+    assert!(cpu.flags.n);
+
+    // LDX #$00; TXA
+    let (mut cpu, mut mem) = setup(&[0xa2, 0x00, 0x8a]);
+    cpu.reg.a = 0x12;
+    run(&mut cpu, &mut mem, 3);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.flags.z);
+
+    // LDY #$01; TYA
+    let (mut cpu, mut mem) = setup(&[0xa0, 0x01, 0x98]);
+    run(&mut cpu, &mut mem, 3);
+    assert_eq!(cpu.reg.a, 0x01);
+}
+
+#[test]
+fn test_stack_pointer_transfers() {
+    // LDX #$80; LDA #$01; TXS
+    let (mut cpu, mut mem) = setup(&[0xa2, 0x80, 0xa9, 0x01, 0x9a]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.sp, 0x80);
+    // TXS does not affect the flags.
+    assert!(!cpu.flags.n);
+
+    // TSX
+    let (mut cpu, mut mem) = setup(&[0xba]);
+    run(&mut cpu, &mut mem, 1);
+    assert_eq!(cpu.reg.x, 0xfd);
+    assert!(cpu.flags.n);
+}
+
+#[test]
+fn test_stack_wraps_within_page_one() {
+    // LDX #$00; TXS; LDA #$42; PHA; PLA
+    let (mut cpu, mut mem) = setup(&[0xa2, 0x00, 0x9a, 0xa9, 0x42, 0x48, 0xa9, 0x00, 0x68]);
+    run(&mut cpu, &mut mem, 6);
+    assert_eq!(mem.read(0x0100), 0x42);
+    assert_eq!(cpu.reg.sp, 0xff);
+    run(&mut cpu, &mut mem, 9);
+    assert_eq!(cpu.reg.a, 0x42);
+    assert_eq!(cpu.reg.sp, 0x00);
+}
+
+#[test]
+fn test_php_and_plp() {
+    // SEC; PHP
+    let (mut cpu, mut mem) = setup(&[0x38, 0x08]);
+    run(&mut cpu, &mut mem, 2);
+    // Bits 4 and 5 are both set on the pushed copy.
+    assert_eq!(mem.read(0x01fd), 0x35);
+
+    // LDA #$ff; PHA; PLP
+    let (mut cpu, mut mem) = setup(&[0xa9, 0xff, 0x48, 0x28]);
+    run(&mut cpu, &mut mem, 4);
+    assert_eq!(cpu.flags.as_byte(), 0xef);
+}
+
+#[test]
+fn test_pla_sets_flags() {
+    // LDA #$00; PHA; LDA #$01; PLA
+    let (mut cpu, mut mem) = setup(&[0xa9, 0x00, 0x48, 0xa9, 0x01, 0x68]);
+    run(&mut cpu, &mut mem, 6);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.flags.z);
+}
+
+#[test]
+fn test_jsr_and_rts() {
+    // JSR $0610; BRK ... $0610: RTS
+    let (mut cpu, mut mem) = setup(&[0x20, 0x10, 0x06]);
+    mem.write(0x0610, 0x60);
+    assert_eq!(cpu.step(&mut mem), 6);
+    assert_eq!(cpu.reg.pc, 0x0610);
+    // The address of the JSR's last byte is pushed.
+    assert_eq!(mem.read(0x01fd), 0x06);
+    assert_eq!(mem.read(0x01fc), 0x02);
+    assert_eq!(cpu.step(&mut mem), 6);
+    assert_eq!(cpu.reg.pc, 0x0603);
+    assert_eq!(cpu.reg.sp, 0xfd);
+}
+
+#[test]
+fn test_brk_and_rti() {
+    // BRK; NOP ... handler: RTI
+    let (mut cpu, mut mem) = setup(&[0x00, 0xea, 0xea]);
+    mem.load(0xfffe, &[0x00, 0x07]);
+    mem.write(0x0700, 0x40);
+    cpu.flags.c = true;
+    cpu.flags.i = false;
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.pc, 0x0700);
+    assert!(cpu.flags.i);
+    cpu.flags.c = false;
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.pc, 0x0602);
+    assert!(cpu.flags.c && !cpu.flags.i);
+}
+
+#[test]
+fn test_jmp_indirect_page_wrap() {
+    // JMP ($02ff)
+    let (mut cpu, mut mem) = setup(&[0x6c, 0xff, 0x02]);
+    mem.write(0x02ff, 0x34);
+    mem.write(0x0200, 0x12);
+    mem.write(0x0300, 0x56);
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.pc, 0x1234);
+}
+
+#[test]
+fn test_branches() {
+    // Not taken: 2 cycles.
+    // BEQ +2
+    let (mut cpu, mut mem) = setup(&[0xf0, 0x02]);
+    cpu.flags.z = false;
+    assert_eq!(cpu.step(&mut mem), 2);
+    assert_eq!(cpu.reg.pc, 0x0602);
+
+    // Taken on the same page: 3 cycles.
+    let (mut cpu, mut mem) = setup(&[0xf0, 0x02]);
+    cpu.flags.z = true;
+    assert_eq!(cpu.step(&mut mem), 3);
+    assert_eq!(cpu.reg.pc, 0x0604);
+
+    // Taken backwards across a page: 4 cycles.
+    // BNE -4
+    let (mut cpu, mut mem) = setup(&[0xd0, 0xfc]);
+    cpu.flags.z = false;
+    assert_eq!(cpu.step(&mut mem), 4);
+    assert_eq!(cpu.reg.pc, 0x05fe);
+}
+
+/// Sets up the flags so that a branch is or is not taken.
+type BranchSetup = fn(&mut Cpu, bool);
+
+#[test]
+fn test_each_branch_condition() {
+    let cases: &[(u8, BranchSetup)] = &[
+        (0x90, |cpu, x| cpu.flags.c = !x),
+        (0xb0, |cpu, x| cpu.flags.c = x),
+        (0xf0, |cpu, x| cpu.flags.z = x),
+        (0xd0, |cpu, x| cpu.flags.z = !x),
+        (0x30, |cpu, x| cpu.flags.n = x),
+        (0x10, |cpu, x| cpu.flags.n = !x),
+        (0x70, |cpu, x| cpu.flags.v = x),
+        (0x50, |cpu, x| cpu.flags.v = !x),
+    ];
+    for &(code, set) in cases {
+        for &taken in &[false, true] {
+            let (mut cpu, mut mem) = setup(&[code, 0x10]);
+            set(&mut cpu, taken);
+            cpu.step(&mut mem);
+            let expected = if taken { 0x0612 } else { 0x0602 };
+            assert_eq!(cpu.reg.pc, expected, "opcode {:02x}", code);
+        }
+    }
+}
+
+#[test]
+fn test_page_crossing_penalty() {
+    // LDA $02ff,X crosses into page 3.
+    let (mut cpu, mut mem) = setup(&[0xbd, 0xff, 0x02]);
+    cpu.reg.x = 1;
+    mem.write(0x0300, 0x42);
+    assert_eq!(cpu.step(&mut mem), 5);
+    assert_eq!(cpu.reg.a, 0x42);
+
+    // LDA ($10),Y crosses into page 3.
+    let (mut cpu, mut mem) = setup(&[0xb1, 0x10]);
+    mem.load(0x10, &[0xff, 0x02]);
+    cpu.reg.y = 1;
+    assert_eq!(cpu.step(&mut mem), 6);
+
+    // STA $02ff,X always takes 5 cycles.
+    let (mut cpu, mut mem) = setup(&[0x9d, 0xff, 0x02]);
+    cpu.reg.x = 1;
+    cpu.reg.a = 0x42;
+    assert_eq!(cpu.step(&mut mem), 5);
+    assert_eq!(mem.read(0x0300), 0x42);
+
+    // INC $02ff,X always takes 7 cycles.
+    let (mut cpu, mut mem) = setup(&[0xfe, 0xff, 0x02]);
+    cpu.reg.x = 1;
+    assert_eq!(cpu.step(&mut mem), 7);
+    assert_eq!(mem.read(0x0300), 0x01);
+}
+
+#[test]
+fn test_zero_page_indexing_wraps() {
+    // LDA $ff,X
+    let (mut cpu, mut mem) = setup(&[0xb5, 0xff]);
+    cpu.reg.x = 2;
+    mem.write(0x0001, 0x42);
+    mem.write(0x0101, 0x24);
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.a, 0x42);
+
+    // STX $ff,Y
+    let (mut cpu, mut mem) = setup(&[0x96, 0xff]);
+    cpu.reg.x = 0x42;
+    cpu.reg.y = 2;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x0001), 0x42);
+}
+
+#[test]
+fn test_stores() {
+    // STA $0200; STX $0201; STY $0202
+    let (mut cpu, mut mem) = setup(&[0x8d, 0x00, 0x02, 0x8e, 0x01, 0x02, 0x8c, 0x02, 0x02]);
+    cpu.reg.a = 1;
+    cpu.reg.x = 2;
+    cpu.reg.y = 3;
+    run(&mut cpu, &mut mem, 9);
+    assert_eq!((mem.read(0x0200), mem.read(0x0201), mem.read(0x0202)), (1, 2, 3));
+}
+
+#[test]
+fn test_flag_instructions() {
+    // SEC; SED; SEI
+    let (mut cpu, mut mem) = setup(&[0x38, 0xf8, 0x78]);
+    cpu.flags.i = false;
+    run(&mut cpu, &mut mem, 3);
+    assert!(cpu.flags.c && cpu.flags.d && cpu.flags.i);
+
+    // CLC; CLD; CLI; CLV
+    let (mut cpu, mut mem) = setup(&[0x18, 0xd8, 0x58, 0xb8]);
+    cpu.flags = Flags::from_byte(0xff);
+    run(&mut cpu, &mut mem, 4);
+    assert!(!cpu.flags.c && !cpu.flags.d && !cpu.flags.i && !cpu.flags.v);
+    assert!(cpu.flags.n && cpu.flags.z);
+}
+
+#[test]
+fn test_every_opcode_decodes() {
+    let official = (0..256)
+        .map(|code| Instruction::from_code(code as u8))
+        .filter(|instruction| instruction.is_official())
+        .count();
+    assert_eq!(official, OFFICIAL_CYCLES.len());
+    for &(code, _) in OFFICIAL_CYCLES {
+        assert!(Instruction::from_code(code).is_official(), "opcode {:02x}", code);
+    }
+}
+
+#[test]
+fn test_unofficial_nops() {
+    // NOP; NOP #$ff; NOP $10; NOP $10,X; NOP $0200; NOP $02ff,X
+    let program = [0x1a, 0x80, 0xff, 0x04, 0x10, 0x14, 0x10, 0x0c, 0x00, 0x02, 0x1c, 0xff, 0x02];
+    let (mut cpu, mut mem) = setup(&program);
+    cpu.reg.x = 1;
+    let cycles: Vec<isize> = (0..6).map(|_| cpu.step(&mut mem)).collect();
+    assert_eq!(cycles, vec![2, 2, 3, 4, 4, 5]);
+    assert_eq!(cpu.reg.pc, ORIGIN + program.len() as u16);
+    assert_eq!((cpu.reg.a, cpu.reg.x), (0, 1));
+}
+
+#[test]
+fn test_lax_and_sax() {
+    // LAX $10
+    let (mut cpu, mut mem) = setup(&[0xa7, 0x10]);
+    mem.write(0x10, 0x80);
+    assert_eq!(cpu.step(&mut mem), 3);
+    assert_eq!((cpu.reg.a, cpu.reg.x), (0x80, 0x80));
+    assert!(cpu.flags.n);
+
+    // LAX $02ff,Y pays for crossing a page.
+    let (mut cpu, mut mem) = setup(&[0xbf, 0xff, 0x02]);
+    cpu.reg.y = 1;
+    mem.write(0x0300, 0x42);
+    assert_eq!(cpu.step(&mut mem), 5);
+    assert_eq!((cpu.reg.a, cpu.reg.x), (0x42, 0x42));
+
+    // SAX $10
+    let (mut cpu, mut mem) = setup(&[0x87, 0x10]);
+    cpu.reg.a = 0xf0;
+    cpu.reg.x = 0x3c;
+    cpu.flags.z = false;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x10), 0x30);
+    assert!(!cpu.flags.z);
+}
+
+#[test]
+fn test_dcp_and_isc() {
+    // DCP $10
+    let (mut cpu, mut mem) = setup(&[0xc7, 0x10]);
+    mem.write(0x10, 0x43);
+    cpu.reg.a = 0x42;
+    assert_eq!(cpu.step(&mut mem), 5);
+    assert_eq!(mem.read(0x10), 0x42);
+    assert!(cpu.flags.z && cpu.flags.c);
+
+    // ISC $10
+    let (mut cpu, mut mem) = setup(&[0xe7, 0x10]);
+    mem.write(0x10, 0x0f);
+    cpu.reg.a = 0x20;
+    cpu.flags.c = true;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x10), 0x10);
+    assert_eq!(cpu.reg.a, 0x10);
+    assert!(cpu.flags.c);
+}
+
+#[test]
+fn test_slo_rla_sre_rra() {
+    // SLO $10
+    let (mut cpu, mut mem) = setup(&[0x07, 0x10]);
+    mem.write(0x10, 0x81);
+    cpu.reg.a = 0x01;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x10), 0x02);
+    assert_eq!(cpu.reg.a, 0x03);
+    assert!(cpu.flags.c);
+
+    // RLA $10
+    let (mut cpu, mut mem) = setup(&[0x27, 0x10]);
+    mem.write(0x10, 0x81);
+    cpu.reg.a = 0xff;
+    cpu.flags.c = true;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x10), 0x03);
+    assert_eq!(cpu.reg.a, 0x03);
+    assert!(cpu.flags.c);
+
+    // SRE $10
+    let (mut cpu, mut mem) = setup(&[0x47, 0x10]);
+    mem.write(0x10, 0x03);
+    cpu.reg.a = 0x01;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x10), 0x01);
+    assert_eq!(cpu.reg.a, 0x00);
+    assert!(cpu.flags.c && cpu.flags.z);
+
+    // RRA $10
+    let (mut cpu, mut mem) = setup(&[0x67, 0x10]);
+    mem.write(0x10, 0x03);
+    cpu.reg.a = 0x10;
+    cpu.flags.c = false;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x10), 0x01);
+    // The carry out of the rotate feeds into the add.
+    assert_eq!(cpu.reg.a, 0x12);
+}
+
+#[test]
+fn test_unofficial_rmw_cycles() {
+    // DCP ($10),Y always takes 8 cycles.
+    let (mut cpu, mut mem) = setup(&[0xd3, 0x10]);
+    assert_eq!(cpu.step(&mut mem), 8);
+    // ISC $0200,X always takes 7 cycles.
+    let (mut cpu, mut mem) = setup(&[0xff, 0x00, 0x02]);
+    assert_eq!(cpu.step(&mut mem), 7);
+}
+
+#[test]
+fn test_unofficial_sbc() {
+    // SEC; LDA #$50; SBC #$f0 using the $eb opcode
+    let (mut cpu, mut mem) = setup(&[0x38, 0xa9, 0x50, 0xeb, 0xf0]);
+    run(&mut cpu, &mut mem, 5);
+    assert_eq!(cpu.reg.a, 0x60);
+    assert!(!cpu.flags.c);
+}
+
+#[test]
+fn test_immediate_combinations() {
+    // ANC #$80
+    let (mut cpu, mut mem) = setup(&[0x0b, 0x80]);
+    cpu.reg.a = 0xff;
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.a, 0x80);
+    assert!(cpu.flags.n && cpu.flags.c);
+
+    // ALR #$03
+    let (mut cpu, mut mem) = setup(&[0x4b, 0x03]);
+    cpu.reg.a = 0xff;
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.a, 0x01);
+    assert!(cpu.flags.c);
+
+    // ARR #$ff with carry set.
+    let (mut cpu, mut mem) = setup(&[0x6b, 0xff]);
+    cpu.reg.a = 0x80;
+    cpu.flags.c = true;
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.a, 0xc0);
+    assert!(cpu.flags.c && cpu.flags.v && cpu.flags.n);
+
+    // AXS #$10
+    let (mut cpu, mut mem) = setup(&[0xcb, 0x10]);
+    cpu.reg.a = 0xf0;
+    cpu.reg.x = 0x3c;
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.x, 0x20);
+    assert!(cpu.flags.c);
+}
+
+#[test]
+fn test_las() {
+    // LAS $0200,Y
+    let (mut cpu, mut mem) = setup(&[0xbb, 0x00, 0x02]);
+    mem.write(0x0200, 0x0f);
+    cpu.step(&mut mem);
+    assert_eq!((cpu.reg.a, cpu.reg.x, cpu.reg.sp), (0x0d, 0x0d, 0x0d));
+}
+
+#[test]
+fn test_unstable_stores() {
+    // SHX $0200,Y stores X & (high byte + 1).
+    let (mut cpu, mut mem) = setup(&[0x9e, 0x00, 0x02]);
+    cpu.reg.x = 0xff;
+    cpu.reg.y = 0x10;
+    assert_eq!(cpu.step(&mut mem), 5);
+    assert_eq!(mem.read(0x0210), 0x03);
+
+    // SHY $02ff,X crossing a page also corrupts the high byte.
+    let (mut cpu, mut mem) = setup(&[0x9c, 0xff, 0x02]);
+    cpu.reg.y = 0x01;
+    cpu.reg.x = 0x02;
+    cpu.step(&mut mem);
+    assert_eq!(mem.read(0x0101), 0x01);
+}
+
+#[test]
+fn test_kil_halts_until_reset() {
+    // KIL
+    let (mut cpu, mut mem) = setup(&[0x02, 0xea]);
+    cpu.step(&mut mem);
+    assert_eq!(cpu.halted(), Some(ORIGIN));
+    assert_eq!(cpu.step(&mut mem), 1);
+    assert_eq!(cpu.reg.pc, ORIGIN + 1);
+
+    // Interrupts cannot wake it, only a reset.
+    cpu.set_nmi(true);
+    cpu.step(&mut mem);
+    assert_eq!(cpu.reg.pc, ORIGIN + 1);
+    cpu.reset(&mut mem);
+    assert_eq!(cpu.halted(), None);
+}
+
+/// A bus access as seen by `Recorder`.
+#[derive(Debug, Eq, PartialEq)]
+enum Access {
+    Read(u16),
+    Write(u16, u8),
+}
+
+/// Records every access to the RAM it wraps, checking
+/// that each one was preceded by exactly one tick.
+struct Recorder {
+    ram: Ram,
+    accesses: Vec<Access>,
+    ticks: usize,
+}
+
+impl Recorder {
+    fn new(ram: Ram) -> Recorder {
+        Recorder {
+            ram,
+            accesses: Vec::new(),
+            ticks: 0,
+        }
+    }
+
+    fn record(&mut self, access: Access) {
+        self.accesses.push(access);
+        assert_eq!(self.ticks, self.accesses.len(), "untimed access: {:?}", self.accesses);
+    }
+}
+
+impl Bus for Recorder {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.record(Access::Read(addr));
+        self.ram.read(addr)
+    }
+
+    fn write(&mut self, addr: u16, x: u8) {
+        self.record(Access::Write(addr, x));
+        self.ram.write(addr, x);
+    }
+
+    fn tick(&mut self) {
+        self.ticks += 1;
+    }
+}
+
+/// Run a single instruction of `program` on a `Recorder`.
+fn record(program: &[u8], setup_cpu: fn(&mut Cpu)) -> (isize, Vec<Access>) {
+    let (mut cpu, ram) = setup(program);
+    setup_cpu(&mut cpu);
+    let mut mem = Recorder::new(ram);
+    let cycles = cpu.step(&mut mem);
+    (cycles, mem.accesses)
+}
+
+#[test]
+fn test_every_cycle_is_a_bus_access() {
+    for code in 0..=0xffu8 {
+        let (cycles, accesses) = record(&[code, 0xff, 0x02], |cpu| cpu.reg.x = 1);
+        assert_eq!(cycles as usize, accesses.len(), "opcode {:02x}", code);
+    }
+}
+
+#[test]
+fn test_indexed_store_dummy_read() {
+    // STA $02ff,X reads from the address before the carry is fixed.
+    let (_, accesses) = record(&[0x9d, 0xff, 0x02], |cpu| {
+        cpu.reg.x = 1;
+        cpu.reg.a = 0x42;
+    });
+    assert_eq!(accesses, vec![
+        Access::Read(ORIGIN),
+        Access::Read(ORIGIN + 1),
+        Access::Read(ORIGIN + 2),
+        Access::Read(0x0200),
+        Access::Write(0x0300, 0x42),
+    ]);
+}
+
+#[test]
+fn test_read_modify_write_dummy_write() {
+    // INC $10 writes back the unmodified value first.
+    let (_, accesses) = record(&[0xe6, 0x10], |_| {});
+    assert_eq!(accesses, vec![
+        Access::Read(ORIGIN),
+        Access::Read(ORIGIN + 1),
+        Access::Read(0x0010),
+        Access::Write(0x0010, 0x00),
+        Access::Write(0x0010, 0x01),
+    ]);
+}
+
+#[test]
+fn test_jsr_and_rts_bus_accesses() {
+    // JSR $0700
+    let (_, accesses) = record(&[0x20, 0x00, 0x07], |_| {});
+    assert_eq!(accesses, vec![
+        Access::Read(ORIGIN),
+        Access::Read(ORIGIN + 1),
+        Access::Read(0x01fd),
+        Access::Write(0x01fd, 0x06),
+        Access::Write(0x01fc, 0x02),
+        Access::Read(ORIGIN + 2),
+    ]);
+
+    // RTS with an empty stack returns to $0001.
+    let (_, accesses) = record(&[0x60], |cpu| cpu.reg.sp = 0xfb);
+    assert_eq!(accesses, vec![
+        Access::Read(ORIGIN),
+        Access::Read(ORIGIN + 1),
+        Access::Read(0x01fb),
+        Access::Read(0x01fc),
+        Access::Read(0x01fd),
+        Access::Read(0x0000),
+    ]);
+}
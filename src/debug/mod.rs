@@ -1,4 +1,6 @@
 mod command;
+pub mod nestest;
+pub mod trace;
 
 use std::io::{Write, stdin, stdout};
 
@@ -14,29 +16,14 @@ pub struct Debugger {
 impl Debugger {
     pub fn new(nes: Nes) -> Debugger {
         Debugger {
-            nes: nes,
+            nes,
             last_command: None,
         }
     }
 
     fn print_instruction(&mut self) {
         let instruction = self.nes.current_instruction();
-        let operand = match instruction.address_mode {
-            AddressMode::Accumulator | AddressMode::Implied => {
-                "".into()
-            },
-            AddressMode::Absolute => format!("${:04x}", self.nes.skip_peek_u16(1)),
-            AddressMode::AbsoluteXIndexed => format!("${:04x},X", self.nes.skip_peek_u16(1)),
-            AddressMode::AbsoluteYIndexed => format!("${:04x},Y", self.nes.skip_peek_u16(1)),
-            AddressMode::Immediate => format!("#${:02x}", self.nes.skip_peek(1)),
-            AddressMode::Relative => format!("${:02x}", self.nes.skip_peek(1)),
-            AddressMode::Indirect => format!("(${:04x})", self.nes.skip_peek_u16(1)),
-            AddressMode::XIndexedIndirect => format!("(${:02x},X)", self.nes.skip_peek(1)),
-            AddressMode::IndirectYIndexed => format!("(${:02x}),Y", self.nes.skip_peek(1)),
-            AddressMode::ZeroPage => format!("${:02x}", self.nes.skip_peek(1)),
-            AddressMode::ZeroPageXIndexed => format!("${:02x},X", self.nes.skip_peek(1)),
-            AddressMode::ZeroPageYIndexed => format!("${:02x},Y", self.nes.skip_peek(1)),
-        };
+        let operand = format_operand(&mut self.nes, instruction.address_mode);
         println!("{:04x} {:?} {}", self.nes.cpu().reg.pc, instruction, operand   );
     }
 
@@ -79,6 +66,27 @@ impl Debugger {
     }
 }
 
+/// Format the operand of the instruction at the PC
+/// in standard 6502 assembler syntax.
+fn format_operand(nes: &mut Nes, address_mode: AddressMode) -> String {
+    match address_mode {
+        AddressMode::Accumulator | AddressMode::Implied => {
+            "".into()
+        },
+        AddressMode::Absolute => format!("${:04x}", nes.skip_peek_u16(1)),
+        AddressMode::AbsoluteXIndexed => format!("${:04x},X", nes.skip_peek_u16(1)),
+        AddressMode::AbsoluteYIndexed => format!("${:04x},Y", nes.skip_peek_u16(1)),
+        AddressMode::Immediate => format!("#${:02x}", nes.skip_peek(1)),
+        AddressMode::Relative => format!("${:02x}", nes.skip_peek(1)),
+        AddressMode::Indirect => format!("(${:04x})", nes.skip_peek_u16(1)),
+        AddressMode::XIndexedIndirect => format!("(${:02x},X)", nes.skip_peek(1)),
+        AddressMode::IndirectYIndexed => format!("(${:02x}),Y", nes.skip_peek(1)),
+        AddressMode::ZeroPage => format!("${:02x}", nes.skip_peek(1)),
+        AddressMode::ZeroPageXIndexed => format!("${:02x},X", nes.skip_peek(1)),
+        AddressMode::ZeroPageYIndexed => format!("${:02x},Y", nes.skip_peek(1)),
+    }
+}
+
 fn read_stdin() -> String {
     let mut input = String::new();
     stdin().read_line(&mut input).unwrap();
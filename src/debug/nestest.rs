@@ -0,0 +1,108 @@
+//! Check the CPU against the nestest ROM: http://www.qmtpro.com/~nes/misc/nestest.txt
+//!
+//! Started at $C000 nestest runs all of its tests without needing
+//! a PPU or controller input, so every instruction executed can be
+//! compared against the canonical Nintendulator log.
+
+use std::fmt;
+
+use super::super::nes::Nes;
+use super::trace::TraceLine;
+
+/// Where nestest's automated test run starts.
+pub const AUTOMATION_START: u16 = 0xc000;
+
+/// The first line at which the CPU differed from the log.
+pub struct Divergence {
+    pub line: usize,
+    pub expected: TraceLine,
+    pub actual: TraceLine,
+}
+
+pub enum Failure {
+    /// Line of the log that could not be parsed.
+    Malformed(usize),
+    Diverged(Box<Divergence>),
+}
+
+impl fmt::Display for Failure {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Failure::Malformed(line) => write!(f, "Malformed log line: {}", line),
+            Failure::Diverged(ref divergence) => {
+                let Divergence { line, ref expected, ref actual } = **divergence;
+                writeln!(f, "Diverged from log at line {} ({}):", line, expected.diff(actual).join(", "))?;
+                writeln!(f, "expected: {}", expected)?;
+                write!(f, "actual:   {}", actual)
+            },
+        }
+    }
+}
+
+/// Run a console with nestest loaded in automation mode, comparing
+/// each instruction executed with the corresponding line of `log`.
+/// Returns the number of lines that matched.
+pub fn run(nes: &mut Nes, log: &str) -> Result<usize, Failure> {
+    nes.cpu_mut().reg.pc = AUTOMATION_START;
+
+    let mut matched = 0;
+    for (i, line) in log.lines().enumerate().filter(|&(_, line)| !line.trim().is_empty()) {
+        let line_number = i + 1;
+        let expected = TraceLine::parse(line).ok_or(Failure::Malformed(line_number))?;
+        let actual = TraceLine::capture(nes);
+        if !expected.diff(&actual).is_empty() {
+            return Err(Failure::Diverged(Box::new(Divergence {
+                line: line_number,
+                expected,
+                actual,
+            })));
+        }
+        nes.step();
+        matched += 1;
+    }
+    Ok(matched)
+}
+
+#[cfg(test)]
+mod tests {
+    use rom::Cartridge;
+    use super::*;
+
+    /// Loops over LDA #$01; LDX #$02 at 0xc000.
+    fn console() -> Nes {
+        let mut prg = vec![0; 0x4000];
+        prg[..7].copy_from_slice(&[0xa9, 0x01, 0xa2, 0x02, 0x4c, 0x00, 0xc0]);
+        Nes::new(Cartridge::with_prg(&prg))
+    }
+
+    const LOG: &str = "\
+C000  A9 01     LDA #$01                        A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+C002  A2 02     LDX #$02                        A:01 X:00 Y:00 P:24 SP:FD PPU:  0, 27 CYC:9
+C004  4C 00 C0  JMP $C000                       A:01 X:02 Y:00 P:24 SP:FD PPU:  0, 33 CYC:11
+C000  A9 01     LDA #$01                        A:01 X:02 Y:00 P:24 SP:FD PPU:  0, 42 CYC:14
+";
+
+    #[test]
+    fn test_run_matches_log() {
+        assert_eq!(run(&mut console(), LOG).ok(), Some(4));
+    }
+
+    #[test]
+    fn test_run_reports_first_divergence() {
+        // The last line expects the wrong X.
+        let log = LOG.replace("X:02 Y:00 P:24 SP:FD PPU:  0, 42",
+                              "X:03 Y:00 P:24 SP:FD PPU:  0, 42");
+        match run(&mut console(), &log) {
+            Err(Failure::Diverged(divergence)) => {
+                assert_eq!(divergence.line, 4);
+                assert_eq!(divergence.expected.diff(&divergence.actual), vec!["X"]);
+                assert_eq!(divergence.actual.x, 0x02);
+            },
+            _ => panic!("expected the run to diverge"),
+        }
+        match run(&mut console(), "C000  garbage") {
+            Err(Failure::Malformed(1)) => (),
+            _ => panic!("expected a malformed line"),
+        }
+    }
+}
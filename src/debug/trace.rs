@@ -0,0 +1,168 @@
+//! Execution traces in the format logged by Nintendulator,
+//! which is the format of the canonical nestest.log:
+//!
+//! C000  4C F5 C5  JMP $C5F5                       A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7
+
+use std::fmt;
+
+use super::super::nes::Nes;
+use super::format_operand;
+
+/// The state of the CPU just before an instruction is run.
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct TraceLine {
+    pub pc: u16,
+    pub bytes: Vec<u8>,
+    pub a: u8,
+    pub x: u8,
+    pub y: u8,
+    pub p: u8,
+    pub sp: u8,
+    pub cycles: u64,
+    // Only used for display, it is not compared as
+    // assemblers disagree on how to format operands.
+    disassembly: String,
+}
+
+impl TraceLine {
+    /// Capture the state of the console before
+    /// the instruction at the PC is run.
+    pub fn capture(nes: &mut Nes) -> TraceLine {
+        let instruction = nes.current_instruction();
+        let bytes = (0..instruction.size() as usize)
+            .map(|i| nes.skip_peek(i))
+            .collect();
+        let operand = format_operand(nes, instruction.address_mode).to_uppercase();
+        let disassembly = format!("{}{:?} {}",
+                                  if instruction.is_official() { " " } else { "*" },
+                                  instruction,
+                                  operand);
+        let cpu = nes.cpu();
+        TraceLine {
+            pc: cpu.reg.pc,
+            bytes,
+            a: cpu.reg.a,
+            x: cpu.reg.x,
+            y: cpu.reg.y,
+            p: cpu.flags.as_byte(),
+            sp: cpu.reg.sp,
+            cycles: cpu.cycles(),
+            disassembly,
+        }
+    }
+
+    /// Parse a line of a Nintendulator log, returning
+    /// `None` if it is not in the expected format.
+    pub fn parse(line: &str) -> Option<TraceLine> {
+        if line.len() < 16 || !line.is_char_boundary(16) {
+            return None;
+        }
+        let pc = u16::from_str_radix(&line[0..4], 16).ok()?;
+        let bytes = line[6..15]
+            .split_whitespace()
+            .map(|byte| u8::from_str_radix(byte, 16))
+            .collect::<Result<Vec<u8>, _>>()
+            .ok()?;
+        if bytes.is_empty() {
+            return None;
+        }
+        let registers = line.find("A:")?;
+        let disassembly = line[15..registers].trim_end().into();
+        let field = |name: &str| -> Option<&str> {
+            line[registers..]
+                .split_whitespace()
+                .find(|field| field.starts_with(name))
+                .map(|field| &field[name.len()..])
+        };
+        let register = |name: &str| -> Option<u8> {
+            u8::from_str_radix(field(name)?, 16).ok()
+        };
+        Some(TraceLine {
+            pc,
+            bytes,
+            a: register("A:")?,
+            x: register("X:")?,
+            y: register("Y:")?,
+            p: register("P:")?,
+            sp: register("SP:")?,
+            cycles: field("CYC:")?.parse().ok()?,
+            disassembly,
+        })
+    }
+
+    /// The names of the fields that differ between two lines.
+    pub fn diff(&self, other: &TraceLine) -> Vec<&'static str> {
+        let fields = [
+            ("PC", self.pc == other.pc),
+            ("opcode", self.bytes == other.bytes),
+            ("A", self.a == other.a),
+            ("X", self.x == other.x),
+            ("Y", self.y == other.y),
+            ("P", self.p == other.p),
+            ("SP", self.sp == other.sp),
+            ("CYC", self.cycles == other.cycles),
+        ];
+        fields.iter()
+            .filter(|&&(_, same)| !same)
+            .map(|&(name, _)| name)
+            .collect()
+    }
+}
+
+impl fmt::Display for TraceLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let bytes = self.bytes.iter()
+            .map(|byte| format!("{:02X}", byte))
+            .collect::<Vec<_>>()
+            .join(" ");
+        write!(f, "{:04X}  {:<8} {:<32} A:{:02X} X:{:02X} Y:{:02X} P:{:02X} SP:{:02X} CYC:{}",
+               self.pc, bytes, self.disassembly,
+               self.a, self.x, self.y, self.p, self.sp, self.cycles)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceLine;
+
+    #[test]
+    fn test_parse() {
+        let line = "C72A  D0 E0     BNE $C70C                       \
+                    A:00 X:00 Y:00 P:27 SP:FB PPU: 15,174 CYC:1781";
+        let trace = TraceLine::parse(line).unwrap();
+        assert_eq!(trace.pc, 0xc72a);
+        assert_eq!(trace.bytes, vec![0xd0, 0xe0]);
+        assert_eq!((trace.a, trace.x, trace.y), (0x00, 0x00, 0x00));
+        assert_eq!((trace.p, trace.sp), (0x27, 0xfb));
+        assert_eq!(trace.cycles, 1781);
+        assert_eq!(trace.disassembly, " BNE $C70C");
+    }
+
+    #[test]
+    fn test_parse_unofficial() {
+        let line = "E54C  07 00    *SLO $00 = 00                    \
+                    A:FF X:FF Y:00 P:A5 SP:FB PPU: 59, 80 CYC:26780";
+        let trace = TraceLine::parse(line).unwrap();
+        assert_eq!(trace.bytes, vec![0x07, 0x00]);
+        assert_eq!(trace.cycles, 26780);
+    }
+
+    #[test]
+    fn test_parse_malformed() {
+        assert_eq!(TraceLine::parse(""), None);
+        assert_eq!(TraceLine::parse("C000  4C F5 C5  JMP $C5F5"), None);
+        assert_eq!(TraceLine::parse("C000  4C F5 C5  JMP $C5F5 A:00 X:00"), None);
+    }
+
+    #[test]
+    fn test_diff() {
+        let line = "C000  4C F5 C5  JMP $C5F5                       \
+                    A:00 X:00 Y:00 P:24 SP:FD PPU:  0, 21 CYC:7";
+        let expected = TraceLine::parse(line).unwrap();
+        let mut actual = expected.clone();
+        assert!(expected.diff(&actual).is_empty());
+        actual.p = 0x04;
+        actual.cycles = 8;
+        assert_eq!(expected.diff(&actual), vec!["P", "CYC"]);
+    }
+}
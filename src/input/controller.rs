@@ -0,0 +1,125 @@
+//! The standard joypad: http://wiki.nesdev.com/w/index.php/Standard_controller
+//!
+//! Writing 1 to bit 0 of 0x4016 holds the strobe line high, which
+//! continuously reloads the shift register of each pad with its
+//! buttons. Once it goes low every read shifts out one button.
+
+use std::any::Any;
+
+use super::{InputDevice, Screen};
+
+/// Which buttons on a joypad are held down.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub struct ButtonState {
+    pub a: bool,
+    pub b: bool,
+    pub select: bool,
+    pub start: bool,
+    pub up: bool,
+    pub down: bool,
+    pub left: bool,
+    pub right: bool,
+}
+
+impl ButtonState {
+    /// The buttons in the order they are read out, A in bit 0.
+    pub fn as_byte(&self) -> u8 {
+        (self.a as u8)
+            | (self.b as u8) << 1
+            | (self.select as u8) << 2
+            | (self.start as u8) << 3
+            | (self.up as u8) << 4
+            | (self.down as u8) << 5
+            | (self.left as u8) << 6
+            | (self.right as u8) << 7
+    }
+}
+
+#[derive(Default)]
+pub struct Controller {
+    buttons: ButtonState,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Controller {
+    pub fn new() -> Controller {
+        Controller::default()
+    }
+
+    pub fn buttons(&self) -> ButtonState {
+        self.buttons
+    }
+
+    pub fn set_buttons(&mut self, buttons: ButtonState) {
+        self.buttons = buttons;
+        self.reload();
+    }
+
+    fn reload(&mut self) {
+        if self.strobe {
+            self.shift = self.buttons.as_byte();
+        }
+    }
+}
+
+impl InputDevice for Controller {
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 != 0;
+        self.reload();
+    }
+
+    /// Read the next button into bit 0. Official pads read
+    /// 1 once all 8 buttons have been shifted out.
+    fn read(&mut self, _screen: &Screen) -> u8 {
+        self.reload();
+        let bit = self.shift & 1;
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0x80;
+        }
+        bit
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read_all(controller: &mut Controller) -> Vec<u8> {
+        (0..10).map(|_| controller.read(&Screen::blank())).collect()
+    }
+
+    #[test]
+    fn test_buttons_shift_out_in_order() {
+        let mut controller = Controller::new();
+        controller.set_buttons(ButtonState { a: true, start: true, left: true, ..Default::default() });
+        controller.write(1);
+        controller.write(0);
+        assert_eq!(read_all(&mut controller), vec![1, 0, 0, 1, 0, 0, 1, 0, 1, 1]);
+    }
+
+    #[test]
+    fn test_strobe_high_reads_a() {
+        let mut controller = Controller::new();
+        controller.write(1);
+        controller.set_buttons(ButtonState { a: true, b: true, ..Default::default() });
+        assert_eq!(read_all(&mut controller), vec![1; 10]);
+        controller.set_buttons(ButtonState { b: true, ..Default::default() });
+        assert_eq!(controller.read(&Screen::blank()), 0);
+    }
+
+    #[test]
+    fn test_buttons_latched_when_strobe_falls() {
+        let mut controller = Controller::new();
+        controller.set_buttons(ButtonState { a: true, ..Default::default() });
+        controller.write(1);
+        controller.write(0);
+        controller.set_buttons(ButtonState { b: true, ..Default::default() });
+        assert_eq!(controller.read(&Screen::blank()), 1);
+        assert_eq!(controller.read(&Screen::blank()), 0);
+    }
+}
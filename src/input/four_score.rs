@@ -0,0 +1,88 @@
+//! The Four Score adapter: http://wiki.nesdev.com/w/index.php/Four_player_adapters
+//!
+//! Each port reads out two pads one after the other, followed by a
+//! signature byte saying which port it is. Put one in each port
+//! for four players.
+
+use std::any::Any;
+
+use super::{ButtonState, InputDevice, Port, Screen};
+
+pub struct FourScore {
+    // Players 1 and 3 on the first port, 2 and 4 on the second.
+    buttons: [ButtonState; 2],
+    signature: u8,
+    strobe: bool,
+    shift: u32,
+}
+
+impl FourScore {
+    pub fn new(port: Port) -> FourScore {
+        FourScore {
+            buttons: [ButtonState::default(); 2],
+            signature: match port {
+                Port::One => 0b0000_1000,
+                Port::Two => 0b0000_0100,
+            },
+            strobe: false,
+            shift: 0,
+        }
+    }
+
+    /// Set the buttons of the first (0) or second (1) pad in this port.
+    pub fn set_buttons(&mut self, pad: usize, buttons: ButtonState) {
+        self.buttons[pad] = buttons;
+        self.reload();
+    }
+
+    fn reload(&mut self) {
+        if self.strobe {
+            self.shift = (self.buttons[0].as_byte() as u32)
+                | (self.buttons[1].as_byte() as u32) << 8
+                | (self.signature as u32) << 16;
+        }
+    }
+}
+
+impl InputDevice for FourScore {
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 != 0;
+        self.reload();
+    }
+
+    /// Reads 1 once the 24 bits have been shifted out.
+    fn read(&mut self, _screen: &Screen) -> u8 {
+        self.reload();
+        let bit = (self.shift & 1) as u8;
+        if !self.strobe {
+            self.shift = (self.shift >> 1) | 0x80_0000;
+        }
+        bit
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_pads_then_signature() {
+        for &(port, signature) in &[(Port::One, [0, 0, 0, 1, 0, 0, 0, 0]),
+                                   (Port::Two, [0, 0, 1, 0, 0, 0, 0, 0])] {
+            let mut four_score = FourScore::new(port);
+            four_score.set_buttons(0, ButtonState { a: true, ..Default::default() });
+            four_score.set_buttons(1, ButtonState { right: true, ..Default::default() });
+            four_score.write(1);
+            four_score.write(0);
+            let bits: Vec<u8> = (0..26).map(|_| four_score.read(&Screen::blank())).collect();
+            assert_eq!(&bits[0..8], &[1, 0, 0, 0, 0, 0, 0, 0]);
+            assert_eq!(&bits[8..16], &[0, 0, 0, 0, 0, 0, 0, 1]);
+            assert_eq!(&bits[16..24], &signature);
+            assert_eq!(&bits[24..26], &[1, 1]);
+        }
+    }
+}
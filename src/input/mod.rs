@@ -0,0 +1,79 @@
+//! Devices plugged into the controller ports:
+//! http://wiki.nesdev.com/w/index.php/Input_devices
+//!
+//! Writes to 0x4016 set the OUT lines shared by both ports, bit 0
+//! being the strobe. Reads of 0x4016 and 0x4017 clock the device in
+//! that port, which drives the low 5 bits of the data bus.
+
+mod controller;
+mod four_score;
+mod power_pad;
+mod vaus;
+mod zapper;
+
+use std::any::Any;
+
+use ppu::Palette;
+
+pub use self::controller::{ButtonState, Controller};
+pub use self::four_score::{FourScore};
+pub use self::power_pad::{PowerPad};
+pub use self::vaus::{Vaus};
+pub use self::zapper::{Zapper};
+
+/// The two controller ports on the front of the console,
+/// read through 0x4016 and 0x4017 respectively.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Port {
+    One,
+    Two,
+}
+
+impl Port {
+    pub fn index(self) -> usize {
+        match self {
+            Port::One => 0,
+            Port::Two => 1,
+        }
+    }
+}
+
+/// What the PPU is drawing when a port is read,
+/// for devices which look at the screen.
+pub struct Screen<'a> {
+    /// The frame being drawn, complete up to the beam, or
+    /// during vblank the frame that has just been finished.
+    pub pixels: &'a [u16],
+    /// The colours the pixels are shown in.
+    pub palette: &'a Palette,
+    pub scanline: u16,
+    pub dot: u16,
+}
+
+#[cfg(test)]
+impl Screen<'static> {
+    /// An empty screen, for devices which don't look at it.
+    pub fn blank() -> Screen<'static> {
+        use std::sync::OnceLock;
+        static PALETTE: OnceLock<Palette> = OnceLock::new();
+        Screen {
+            pixels: &[],
+            palette: PALETTE.get_or_init(Palette::ntsc),
+            scanline: 0,
+            dot: 0,
+        }
+    }
+}
+
+pub trait InputDevice {
+    /// Set the OUT lines, the low 3 bits of a write to 0x4016.
+    fn write(&mut self, out: u8);
+
+    /// Clock the device for a read of its port, returning
+    /// the bits it drives onto D0-D4 of the data bus.
+    fn read(&mut self, screen: &Screen) -> u8;
+
+    /// Lets frontends get back to the concrete device plugged
+    /// into a port to change its buttons, aim and so on.
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
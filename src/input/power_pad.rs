@@ -0,0 +1,85 @@
+//! The Power Pad: http://wiki.nesdev.com/w/index.php/Power_Pad
+//!
+//! Its 12 buttons are read out through two shift registers at once,
+//! 8 of them on D3 and the other 4 on D4, which then read 1.
+
+use std::any::Any;
+
+use super::{InputDevice, Screen};
+
+/// The buttons shifted out on D3 and D4, numbered as on side B.
+const D3_BUTTONS: [usize; 8] = [2, 1, 5, 9, 6, 10, 11, 7];
+const D4_BUTTONS: [usize; 4] = [4, 3, 12, 8];
+
+#[derive(Default)]
+pub struct PowerPad {
+    buttons: [bool; 12],
+    strobe: bool,
+    d3: u8,
+    d4: u8,
+}
+
+impl PowerPad {
+    pub fn new() -> PowerPad {
+        PowerPad::default()
+    }
+
+    /// Which of buttons 1 to 12 are being stood on.
+    pub fn set_buttons(&mut self, buttons: [bool; 12]) {
+        self.buttons = buttons;
+        self.reload();
+    }
+
+    fn reload(&mut self) {
+        if self.strobe {
+            let buttons = self.buttons;
+            let bits = |order: &[usize]| {
+                order.iter()
+                    .enumerate()
+                    .fold(0, |bits, (i, &button)| bits | (buttons[button - 1] as u8) << i)
+            };
+            self.d3 = bits(&D3_BUTTONS);
+            self.d4 = bits(&D4_BUTTONS) | 0xf0;
+        }
+    }
+}
+
+impl InputDevice for PowerPad {
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 != 0;
+        self.reload();
+    }
+
+    fn read(&mut self, _screen: &Screen) -> u8 {
+        self.reload();
+        let bits = (self.d3 & 1) << 3 | (self.d4 & 1) << 4;
+        if !self.strobe {
+            self.d3 = (self.d3 >> 1) | 0x80;
+            self.d4 = (self.d4 >> 1) | 0x80;
+        }
+        bits
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_buttons_on_both_lines() {
+        let mut power_pad = PowerPad::new();
+        let mut buttons = [false; 12];
+        buttons[0] = true;
+        buttons[11] = true;
+        power_pad.set_buttons(buttons);
+        power_pad.write(1);
+        power_pad.write(0);
+        let d3: Vec<u8> = (0..10).map(|_| power_pad.read(&Screen::blank())).collect();
+        // Button 1 is second on D3, button 12 third on D4.
+        assert_eq!(d3, vec![0x00, 0x08, 0x10, 0x00, 0x10, 0x10, 0x10, 0x10, 0x18, 0x18]);
+    }
+}
@@ -0,0 +1,95 @@
+//! The Arkanoid controller: http://wiki.nesdev.com/w/index.php/Arkanoid_controller
+//!
+//! The NES version latches the position of its knob when the strobe
+//! falls and shifts it out inverted, most significant bit first, on
+//! D3 of its port. D4 is the fire button.
+
+use std::any::Any;
+
+use super::{InputDevice, Screen};
+
+/// The range the knob covers, as Arkanoid reads it.
+pub const MIN_POSITION: u8 = 98;
+pub const MAX_POSITION: u8 = 242;
+
+pub struct Vaus {
+    position: u8,
+    button: bool,
+    strobe: bool,
+    shift: u8,
+}
+
+impl Default for Vaus {
+    fn default() -> Self {
+        Vaus {
+            position: MIN_POSITION,
+            button: false,
+            strobe: false,
+            shift: 0,
+        }
+    }
+}
+
+impl Vaus {
+    pub fn new() -> Vaus {
+        Vaus::default()
+    }
+
+    /// Turn the knob, clamped to the range it can reach.
+    pub fn set_position(&mut self, position: u8) {
+        self.position = position.clamp(MIN_POSITION, MAX_POSITION);
+    }
+
+    pub fn set_button(&mut self, pressed: bool) {
+        self.button = pressed;
+    }
+}
+
+impl InputDevice for Vaus {
+    fn write(&mut self, out: u8) {
+        self.strobe = out & 1 != 0;
+        if self.strobe {
+            self.shift = !self.position;
+        }
+    }
+
+    fn read(&mut self, _screen: &Screen) -> u8 {
+        let bit = self.shift >> 7;
+        if !self.strobe {
+            self.shift <<= 1;
+        }
+        bit << 3 | (self.button as u8) << 4
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_position_is_inverted_msb_first() {
+        let mut vaus = Vaus::new();
+        vaus.set_position(0b1010_0000);
+        vaus.set_button(true);
+        vaus.write(1);
+        vaus.write(0);
+        // Moving the knob after the strobe falls has no effect.
+        vaus.set_position(MAX_POSITION);
+        let bits: Vec<u8> = (0..8).map(|_| vaus.read(&Screen::blank())).collect();
+        assert_eq!(bits, vec![0x10, 0x18, 0x10, 0x18, 0x18, 0x18, 0x18, 0x18]);
+    }
+
+    #[test]
+    fn test_position_is_clamped() {
+        let mut vaus = Vaus::new();
+        vaus.set_position(0);
+        vaus.write(1);
+        vaus.write(0);
+        let bits: Vec<u8> = (0..8).map(|_| vaus.read(&Screen::blank()) >> 3).collect();
+        assert_eq!(bits, vec![1, 0, 0, 1, 1, 1, 0, 1]);
+    }
+}
@@ -0,0 +1,150 @@
+//! The Zapper light gun: http://wiki.nesdev.com/w/index.php/Zapper
+//!
+//! The photodiode sees light when the gun is aimed at a bright part
+//! of the picture, for a short while after the beam has drawn it.
+//! D3 reads 0 while light is seen and D4 reads 1 while the trigger
+//! is pulled.
+
+use std::any::Any;
+
+use ppu::{WIDTH, HEIGHT};
+use super::{InputDevice, Screen};
+
+/// Scanlines the photodiode keeps responding after the beam passes.
+const LIGHT_SCANLINES: usize = 26;
+
+/// Luminance, out of 255, a colour needs for the gun to see it.
+const BRIGHTNESS_THRESHOLD: u32 = 0x80;
+
+#[derive(Default)]
+pub struct Zapper {
+    // Pixel the gun is pointed at, or `None` if off screen.
+    aim: Option<(usize, usize)>,
+    trigger: bool,
+}
+
+impl Zapper {
+    pub fn new() -> Zapper {
+        Zapper::default()
+    }
+
+    pub fn set_aim(&mut self, aim: Option<(usize, usize)>) {
+        self.aim = aim.filter(|&(x, y)| x < WIDTH && y < HEIGHT);
+    }
+
+    pub fn set_trigger(&mut self, pulled: bool) {
+        self.trigger = pulled;
+    }
+
+    fn light_sensed(&self, screen: &Screen) -> bool {
+        let (x, y) = match self.aim {
+            Some(aim) => aim,
+            None => return false,
+        };
+        // Pixel x is drawn on dot x + 1, and `dot` is the next to run.
+        let (scanline, dot) = (screen.scanline as usize, screen.dot as usize);
+        let drawn = scanline > y || (scanline == y && dot > x + 1);
+        if !drawn || scanline - y >= LIGHT_SCANLINES {
+            return false;
+        }
+        let [r, g, b] = match screen.pixels.get(y * WIDTH + x) {
+            Some(&index) => screen.palette.rgb(index),
+            None => return false,
+        };
+        let luminance = (r as u32 * 299 + g as u32 * 587 + b as u32 * 114) / 1000;
+        luminance >= BRIGHTNESS_THRESHOLD
+    }
+}
+
+impl InputDevice for Zapper {
+    fn write(&mut self, _out: u8) {}
+
+    fn read(&mut self, screen: &Screen) -> u8 {
+        (!self.light_sensed(screen) as u8) << 3 | (self.trigger as u8) << 4
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ppu::{Palette, Ppu, PpuInterface};
+    use rom::{Cartridge, Mirroring};
+    use super::*;
+
+    fn read_at(zapper: &mut Zapper, pixels: &[u16], scanline: u16, dot: u16) -> u8 {
+        let palette = Screen::blank().palette;
+        zapper.read(&Screen { pixels, palette, scanline, dot })
+    }
+
+    #[test]
+    fn test_light_follows_the_beam() {
+        let mut pixels = vec![0x0f; WIDTH * HEIGHT];
+        pixels[100 * WIDTH + 50] = 0x30;
+        let mut zapper = Zapper::new();
+        zapper.set_aim(Some((50, 100)));
+        // Not drawn yet.
+        assert_eq!(read_at(&mut zapper, &pixels, 100, 50), 0x08);
+        assert_eq!(read_at(&mut zapper, &pixels, 100, 51), 0x08);
+        assert_eq!(read_at(&mut zapper, &pixels, 100, 52), 0x00);
+        assert_eq!(read_at(&mut zapper, &pixels, 120, 0), 0x00);
+        // Faded by the time the beam is well past.
+        assert_eq!(read_at(&mut zapper, &pixels, 130, 0), 0x08);
+    }
+
+    #[test]
+    fn test_dark_pixels_and_trigger() {
+        let pixels = vec![0x0f; WIDTH * HEIGHT];
+        let mut zapper = Zapper::new();
+        zapper.set_aim(Some((50, 100)));
+        zapper.set_trigger(true);
+        assert_eq!(read_at(&mut zapper, &pixels, 110, 0), 0x18);
+        zapper.set_aim(Some((WIDTH, 0)));
+        assert_eq!(read_at(&mut zapper, &pixels, 110, 0), 0x18);
+    }
+
+    #[test]
+    fn test_brightness_uses_the_screen_palette() {
+        let pixels = vec![0x0f; WIDTH * HEIGHT];
+        let mut zapper = Zapper::new();
+        zapper.set_aim(Some((50, 100)));
+        // Black in the NTSC palette, white in this one.
+        let palette = Palette::from_bytes(&[0xff; 64 * 3]).unwrap();
+        let screen = Screen { pixels: &pixels, palette: &palette, scanline: 110, dot: 0 };
+        assert_eq!(zapper.read(&screen), 0x00);
+    }
+
+    fn step_to(ppu: &mut Ppu, interface: &mut PpuInterface, cartridge: &mut Cartridge, scanline: u16, dot: u16) {
+        while !(ppu.scanline() == scanline && ppu.dot() == dot) {
+            ppu.step(interface, cartridge);
+        }
+    }
+
+    #[test]
+    fn test_pixel_is_only_seen_once_drawn() {
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        let mut interface = PpuInterface::new();
+        let mut ppu = Ppu::new();
+        let mut zapper = Zapper::new();
+        zapper.set_aim(Some((50, 100)));
+
+        // Fill both buffers with white frames.
+        interface.write(&mut cartridge, 0x3f00, 0x30);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 100, 52);
+        assert_eq!(read_at(&mut zapper, ppu.beam_pixels(), 100, 52), 0x00);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 0, 0);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 0, 1);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 0, 0);
+
+        // The next frame is black, and at dot x + 1 the
+        // pixel under the beam is still the stale white.
+        interface.write(&mut cartridge, 0x3f00, 0x0f);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 100, 51);
+        assert_eq!(ppu.beam_pixels()[100 * WIDTH + 50], 0x30);
+        assert_eq!(read_at(&mut zapper, ppu.beam_pixels(), 100, 51), 0x08);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 100, 52);
+        assert_eq!(read_at(&mut zapper, ppu.beam_pixels(), 100, 52), 0x08);
+    }
+}
@@ -1,50 +1,100 @@
+use std::any::Any;
+
+use apu::ApuInterface;
+use cpu::Bus;
+use input::{ButtonState, Controller, InputDevice, Port, Screen};
 use rom::{Cartridge};
-use ppu::{PpuInterface};
+use ppu::{Palette, Ppu, PpuInterface};
+use region::Region;
 
 pub struct Interconnect {
     ram: Vec<u8>,
     cartridge: Cartridge,
+    ppu: Ppu,
     ppu_interface: PpuInterface,
-    dma: bool,
+    apu_interface: ApuInterface,
+    ports: [Box<dyn InputDevice>; 2],
+    // The colours frames are shown in, which
+    // light guns judge brightness by.
+    palette: Palette,
+    // Page of the pending sprite DMA, if one was requested.
+    sprite_dma_page: Option<u8>,
+    // The cycle the DMC asked for a sample byte on, if it
+    // is waiting for a DMA to fetch it.
+    dmc_dma_requested: Option<u64>,
+    // The last value on the CPU's data bus, which is what
+    // is read back from addresses nothing responds to.
+    open_bus: u8,
+    // Number of CPU cycles the bus has been clocked for.
+    cycles: u64,
+    // Level of the PPU's NMI output when last looked at.
+    nmi_level: bool,
+    // Set on a rising edge of the NMI line, until
+    // the CPU has been told about it.
+    nmi_edge: bool,
 }
 
 impl Interconnect {
     pub fn new(cartridge: Cartridge) -> Self {
         Interconnect {
             ram: vec![0; 0x2000],
-            cartridge: cartridge,
+            cartridge,
+            ppu: Ppu::new(),
             ppu_interface: PpuInterface::new(),
-            dma: false,
+            apu_interface: ApuInterface::new(Region::NTSC),
+            ports: [Box::new(Controller::new()), Box::new(Controller::new())],
+            palette: Palette::default(),
+            sprite_dma_page: None,
+            dmc_dma_requested: None,
+            open_bus: 0,
+            cycles: 0,
+            nmi_level: false,
+            nmi_edge: false,
         }
     }
 
     pub fn cpu_read(&mut self, addr: u16) -> u8 {
+        let x = self.cpu_read_unlatched(addr);
+        self.open_bus = x;
+        x
+    }
+
+    fn cpu_read_unlatched(&mut self, addr: u16) -> u8 {
         match addr {
             // RAM
-            0x0000 ... 0x1fff => {
+            0x0000 ..= 0x1fff => {
                 // RAM mirrored from 0x0800 to 0x2000.
                 let offset = addr % 0x0800;
                 self.ram[offset as usize]
             },
             // PPU Registers
-            0x2000 ... 0x3fff => {
-                self.ppu_interface.read_register(addr)
+            0x2000 ..= 0x3fff => {
+                self.ppu_interface.read_register(&mut self.cartridge, addr)
             },
-            0x4014 => {
-                panic!("Cannot read from write-only PPU DMA register")
-            },
-            // I/O Registers
-            0x4000 ... 0x4013 | 0x4015 ... 0x4017 => {
-                panic!("I/O reads not implemented: {:#x}", addr)
+            // Write-only APU and DMA registers
+            0x4000 ..= 0x4014 => self.open_bus,
+            0x4015 => {
+                (self.apu_interface.read_status() & !0x20) | (self.open_bus & 0x20)
             },
-            // Expansion ROM
-            0x4020 ... 0x5fff => {
-                panic!("Expansion ROM reads not implemented: {:#x}", addr)
+            // Input devices only drive the low bits of the data
+            // bus, the rest is left over from the last access.
+            0x4016 ..= 0x4017 => {
+                let screen = Screen {
+                    pixels: self.ppu.beam_pixels(),
+                    palette: &self.palette,
+                    scanline: self.ppu.scanline(),
+                    dot: self.ppu.dot(),
+                };
+                let device = &mut self.ports[(addr - 0x4016) as usize];
+                (self.open_bus & 0xe0) | (device.read(&screen) & 0x1f)
             },
-            0x6000 ... 0xffff => {
-                self.cartridge.read(addr)
+            // The CPU's test mode registers, which are
+            // disabled on a retail console.
+            0x4018 ..= 0x401f => self.open_bus,
+            // Anything the cartridge doesn't drive is open bus.
+            0x4020 ..= 0xffff => {
+                self.cartridge.cpu_read(addr).unwrap_or(self.open_bus)
             },
-            _ => panic!("Invalid write to memory at: {:#x}", addr),
         }
     }
 
@@ -55,46 +105,181 @@ impl Interconnect {
     }
 
     pub fn cpu_write(&mut self, addr: u16, x: u8) {
+        self.open_bus = x;
         match addr {
             // RAM
-            0x0000 ... 0x1fff => {
+            0x0000 ..= 0x1fff => {
                 let offset = addr % 0x0800;
                 self.ram[offset as usize] = x;
             },
             // PPU Registers
-            0x2000 ... 0x3fff => {
-                self.ppu_interface.write_register(addr, x);
+            0x2000 ..= 0x3fff => {
+                self.ppu_interface.write_register(&mut self.cartridge, addr, x);
             },
             0x4014 => {
-                // Perform Sprite DMA process.
-                let dma_addr = (x * 0x100) as u16;
-                for i in 0..256 {
-                    let value = self.cpu_read(dma_addr + i);
-                    self.ppu_interface.write_spr(i as u8, value);
-                }
+                // The CPU is halted for the transfer once
+                // it finishes the current instruction.
+                self.sprite_dma_page = Some(x);
             },
-            // I/O Registers
-            0x4000 ... 0x4013 | 0x4015 ... 0x4017 => {
-                panic!("I/O reads not implemented: {:#x}", addr)
+            // The OUT lines are shared by both ports.
+            0x4016 => {
+                for device in &mut self.ports {
+                    device.write(x & 0b111);
+                }
             },
-            // Expansion ROM
-            0x4020 ... 0x5fff => {
-                panic!("Expansion ROM writes not implemented")
+            // APU Registers
+            0x4000 ..= 0x4013 | 0x4015 | 0x4017 => {
+                self.apu_interface.write_register(addr, x);
             },
-            0x6000 ... 0xffff => {
-                self.cartridge.write(addr, x);
+            0x4018 ..= 0x401f => {},
+            0x4020 ..= 0xffff => {
+                self.cartridge.cpu_write(addr, x);
             },
-            _ => unreachable!(),
         }
     }
 
     pub fn ppu_read(&self, addr: u16) -> u8 {
-        self.ppu_interface.read(addr)
+        self.ppu_interface.read(&self.cartridge, addr)
+    }
+
+    /// Whether the NMI line, driven by the PPU, has gone from low to
+    /// high since the last call. The CPU only looks between instructions,
+    /// so edges are caught on every cycle rather than from the level
+    /// then, which would miss the line dropping and rising again.
+    pub fn nmi(&mut self) -> bool {
+        self.poll_nmi();
+        let edge = self.nmi_edge;
+        self.nmi_edge = false;
+        edge
+    }
+
+    fn poll_nmi(&mut self) {
+        let level = self.ppu_interface.nmi();
+        if level && !self.nmi_level {
+            self.nmi_edge = true;
+        }
+        self.nmi_level = level;
+    }
+
+    /// Level of the CPU's IRQ line, raised by the APU or the mapper.
+    pub fn irq(&self) -> bool {
+        self.apu_interface.irq() || self.cartridge.irq()
+    }
+
+    pub fn sprite_dma_pending(&self) -> bool { self.sprite_dma_page.is_some() }
+
+    /// Copy the page requested through 0x4014 into SPR RAM, starting
+    /// at the address set through 0x2003 and wrapping around, while
+    /// clocking the bus for every read and write. The transfer
+    /// takes 513 cycles, plus one to align with the write cycle
+    /// when it starts on an odd cycle. Returns the cycles taken.
+    pub fn sprite_dma(&mut self) -> usize {
+        let page = match self.sprite_dma_page.take() {
+            Some(page) => page,
+            None => return 0,
+        };
+        let start = self.cycles;
+
+        self.tick();
+        if self.cycles % 2 == 1 {
+            self.tick();
+        }
+        let dma_addr = (page as u16) << 8;
+        for i in 0..256 {
+            // A DMC fetch slots in between the sprite
+            // DMA's reads, taking 2 cycles of its own.
+            if self.dmc_dma_requested.is_some() {
+                self.dmc_dma(2);
+            }
+            self.tick();
+            let value = self.cpu_read(dma_addr + i);
+            self.tick();
+            self.ppu_interface.write_spr_data(value);
+        }
+        (self.cycles - start) as usize
+    }
+
+    /// Fetch the DMC's next sample byte, clocking the bus
+    /// for `cycles` cycles with the last being the read.
+    fn dmc_dma(&mut self, cycles: u64) {
+        for _ in 0..cycles {
+            self.tick();
+        }
+        if let Some(addr) = self.apu_interface.dmc_dma_address() {
+            let value = self.cpu_read(addr);
+            self.apu_interface.dmc_fill(value);
+        }
+        self.dmc_dma_requested = None;
     }
 
-    pub fn dma(&self) -> bool { self.dma }
+    pub fn ppu(&self) -> &Ppu { &self.ppu }
 
-    pub fn set_dma(&mut self, value: bool) { self.dma = value; }
+    pub fn apu(&self) -> &ApuInterface { &self.apu_interface }
+
+    pub fn apu_mut(&mut self) -> &mut ApuInterface { &mut self.apu_interface }
+
+    pub fn palette(&self) -> &Palette { &self.palette }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+    }
+
+    /// Plug a device into a port in place of whatever was there.
+    pub fn plug(&mut self, port: Port, device: Box<dyn InputDevice>) {
+        self.ports[port.index()] = device;
+    }
+
+    /// The device plugged into a port, if it is a `T`.
+    pub fn device_mut<T: Any>(&mut self, port: Port) -> Option<&mut T> {
+        self.ports[port.index()].as_any_mut().downcast_mut()
+    }
+
+    /// Hold down the buttons of the pad plugged into a port.
+    /// Does nothing if another kind of device is plugged in.
+    pub fn set_buttons(&mut self, port: Port, buttons: ButtonState) {
+        if let Some(controller) = self.device_mut::<Controller>(port) {
+            controller.set_buttons(buttons);
+        }
+    }
+
+    /// The number of CPU cycles the bus has been clocked for.
+    pub fn cycles(&self) -> u64 { self.cycles }
+}
+
+impl Bus for Interconnect {
+    fn read(&mut self, addr: u16) -> u8 {
+        self.cpu_read(addr)
+    }
+
+    fn write(&mut self, addr: u16, x: u8) {
+        self.cpu_write(addr, x);
+    }
+
+    /// A DMC fetch halts the CPU for a halt cycle, a dummy cycle, an
+    /// alignment cycle and the read itself. Any write cycles since the
+    /// fetch was asked for overlap the first of those, so the stall
+    /// takes 1 to 4 cycles.
+    fn halt(&mut self) {
+        if let Some(requested) = self.dmc_dma_requested {
+            let writes = self.cycles - requested;
+            self.dmc_dma(4u64.saturating_sub(writes).max(1));
+        }
+    }
+
+    /// The PPU runs 3 dots for every CPU cycle, and
+    /// the APU is clocked at the same rate as the CPU.
+    fn tick(&mut self) {
+        self.cycles += 1;
+        self.apu_interface.step();
+        self.cartridge.tick();
+        if self.dmc_dma_requested.is_none() && self.apu_interface.dmc_dma_address().is_some() {
+            self.dmc_dma_requested = Some(self.cycles);
+        }
+        for _ in 0..3 {
+            self.ppu.step(&mut self.ppu_interface, &mut self.cartridge);
+        }
+        self.poll_nmi();
+    }
 }
 
 
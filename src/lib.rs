@@ -0,0 +1,14 @@
+#![allow(clippy::module_inception)]
+
+#[macro_use]
+extern crate nom;
+
+pub mod apu;
+pub mod cpu;
+pub mod debug;
+pub mod input;
+pub mod interconnect;
+pub mod nes;
+pub mod ppu;
+pub mod region;
+pub mod rom;
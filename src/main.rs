@@ -1,56 +1,243 @@
+extern crate byteorder;
 extern crate clap;
-#[macro_use]
-extern crate nom;
-
-mod cpu;
-mod debug;
-mod interconnect;
-mod nes;
-mod ppu;
-mod region;
-mod rom;
+extern crate nes;
+extern crate png;
 
 use std::fs::File;
-use std::io::Read;
+use std::io;
+use std::io::{BufWriter, Read, Write};
 use std::path::{Path, PathBuf};
+use std::process;
 
+use byteorder::{LittleEndian, WriteBytesExt};
 use clap::{Arg, App, SubCommand};
 
-use cpu::Cpu;
-use nes::Nes;
-use rom::Cartridge;
+use nes::debug;
+use nes::input::{Controller, FourScore, InputDevice, Port, PowerPad, Vaus, Zapper};
+use nes::nes::Nes;
+use nes::ppu::{Palette, WIDTH, HEIGHT};
+use nes::rom::Cartridge;
 
 fn create_console<P: AsRef<Path>>(filename: P) -> Nes {
     let cartridge = match Cartridge::from_file(&filename) {
         Ok(c) => c,
-        Err(e) => panic!("{}", e),
+        Err(e) => {
+            println!("{}: {}", filename.as_ref().display(), e);
+            process::exit(1);
+        },
     };
     Nes::new(cartridge)
 }
 
+/// Write the console's latest frame as an RGB PNG.
+fn write_screenshot<P: AsRef<Path>>(console: &Nes, path: P) -> io::Result<()> {
+    let file = File::create(path)?;
+    let mut encoder = png::Encoder::new(BufWriter::new(file), WIDTH as u32, HEIGHT as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&console.frame_rgb())?;
+    Ok(())
+}
+
+fn save_screenshot<P: AsRef<Path>>(console: &Nes, path: P) {
+    if let Err(e) = write_screenshot(console, &path) {
+        println!("{}: {}", path.as_ref().display(), e);
+        process::exit(1);
+    }
+}
+
+/// Write mono samples from -1 to 1 as a 16-bit PCM WAV.
+fn write_wav<P: AsRef<Path>>(path: P, sample_rate: u32, samples: &[f32]) -> io::Result<()> {
+    let mut out = BufWriter::new(File::create(path)?);
+    let data_size = samples.len() as u32 * 2;
+    out.write_all(b"RIFF")?;
+    out.write_u32::<LittleEndian>(36 + data_size)?;
+    out.write_all(b"WAVE")?;
+
+    out.write_all(b"fmt ")?;
+    out.write_u32::<LittleEndian>(16)?;
+    // PCM, 1 channel, the byte rate, block size and bits per sample.
+    out.write_u16::<LittleEndian>(1)?;
+    out.write_u16::<LittleEndian>(1)?;
+    out.write_u32::<LittleEndian>(sample_rate)?;
+    out.write_u32::<LittleEndian>(sample_rate * 2)?;
+    out.write_u16::<LittleEndian>(2)?;
+    out.write_u16::<LittleEndian>(16)?;
+
+    out.write_all(b"data")?;
+    out.write_u32::<LittleEndian>(data_size)?;
+    for &sample in samples {
+        out.write_i16::<LittleEndian>((sample.clamp(-1.0, 1.0) * 32767.0) as i16)?;
+    }
+    out.flush()
+}
+
+/// `out.png` becomes `out-00042.png` for frame 42.
+fn numbered(path: &Path, frame: u64) -> PathBuf {
+    let stem = path.file_stem().map(|stem| stem.to_string_lossy()).unwrap_or_default();
+    let mut name = format!("{}-{:05}", stem, frame);
+    if let Some(extension) = path.extension() {
+        name = format!("{}.{}", name, extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+const DEVICES: [&str; 5] = ["pad", "zapper", "four-score", "vaus", "power-pad"];
+
+fn create_device(name: &str, port: Port) -> Box<dyn InputDevice> {
+    match name {
+        "zapper" => Box::new(Zapper::new()),
+        "four-score" => Box::new(FourScore::new(port)),
+        "vaus" => Box::new(Vaus::new()),
+        "power-pad" => Box::new(PowerPad::new()),
+        _ => Box::new(Controller::new()),
+    }
+}
+
+fn positive(value: String) -> Result<(), String> {
+    match value.parse::<u64>() {
+        Ok(n) if n > 0 => Ok(()),
+        _ => Err(format!("expected a positive number, got {}", value)),
+    }
+}
+
 fn main() {
     let opts = App::new("nes")
         .version("0.1")
         .subcommand(SubCommand::with_name("emu")
             .arg(Arg::with_name("FILENAME")
-                .required(true)))
+                .required(true))
+            .arg(Arg::with_name("palette")
+                .long("palette")
+                .takes_value(true)
+                .value_name("FILE")
+                .help("Colours from a 192 or 1536 byte .pal file"))
+            .arg(Arg::with_name("port1")
+                .long("port1")
+                .takes_value(true)
+                .value_name("DEVICE")
+                .possible_values(&DEVICES)
+                .help("Device plugged into the first controller port"))
+            .arg(Arg::with_name("port2")
+                .long("port2")
+                .takes_value(true)
+                .value_name("DEVICE")
+                .possible_values(&DEVICES)
+                .help("Device plugged into the second controller port"))
+            .arg(Arg::with_name("frames")
+                .long("frames")
+                .takes_value(true)
+                .value_name("N")
+                .validator(positive)
+                .help("Exit after running N frames"))
+            .arg(Arg::with_name("screenshot")
+                .long("screenshot")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("frames")
+                .help("Write the last frame to a PNG on exit"))
+            .arg(Arg::with_name("screenshot-every")
+                .long("screenshot-every")
+                .takes_value(true)
+                .value_name("K")
+                .validator(positive)
+                .requires("screenshot")
+                .help("Instead write every Kth frame, numbering the files"))
+            .arg(Arg::with_name("wav")
+                .long("wav")
+                .takes_value(true)
+                .value_name("FILE")
+                .requires("frames")
+                .help("Write the audio to a 16-bit WAV on exit")))
         .subcommand(SubCommand::with_name("dbg")
             .arg(Arg::with_name("FILENAME")
                 .required(true)))
+        .subcommand(SubCommand::with_name("nestest")
+            .about("Check the CPU against nestest.nes and its Nintendulator log")
+            .arg(Arg::with_name("FILENAME")
+                .required(true))
+            .arg(Arg::with_name("LOG")
+                .required(true)))
         .get_matches();
 
     match opts.subcommand() {
         ("emu", Some(subopts)) => {
             let filename = subopts.value_of("FILENAME").unwrap();
-            let mut console = create_console(&filename);
-            console.run();
+            let mut console = create_console(filename);
+            if let Some(path) = subopts.value_of("palette") {
+                match Palette::from_file(path) {
+                    Ok(palette) => console.set_palette(palette),
+                    Err(e) => {
+                        println!("{}: {}", path, e);
+                        process::exit(1);
+                    },
+                }
+            }
+            for &(name, port) in &[("port1", Port::One), ("port2", Port::Two)] {
+                if let Some(device) = subopts.value_of(name) {
+                    console.plug(port, create_device(device, port));
+                }
+            }
+            // Both counts have already been checked by `positive`.
+            let frames: u64 = match subopts.value_of("frames") {
+                Some(frames) => frames.parse().unwrap(),
+                None => {
+                    console.run(None);
+                    return;
+                },
+            };
+            let screenshot = subopts.value_of("screenshot").map(Path::new);
+            let every = subopts.value_of("screenshot-every")
+                .map(|every| every.parse::<u64>().unwrap());
+            let wav = subopts.value_of("wav");
+            let mut samples = Vec::new();
+            for frame in 1..=frames {
+                console.run_frame();
+                if wav.is_some() {
+                    console.drain_audio(&mut samples);
+                }
+                if let (Some(path), Some(every)) = (screenshot, every) {
+                    if frame % every == 0 {
+                        save_screenshot(&console, numbered(path, frame));
+                    }
+                }
+            }
+            if let (Some(path), None) = (screenshot, every) {
+                save_screenshot(&console, path);
+            }
+            if let Some(path) = wav {
+                if let Err(e) = write_wav(path, console.sample_rate(), &samples) {
+                    println!("{}: {}", path, e);
+                    process::exit(1);
+                }
+            }
         },
         ("dbg", Some(subopts)) => {
             let filename = subopts.value_of("FILENAME").unwrap();
-            let console = create_console(&filename);
+            let console = create_console(filename);
             let mut debugger = debug::Debugger::new(console);
             debugger.run();
         },
+        ("nestest", Some(subopts)) => {
+            let filename = subopts.value_of("FILENAME").unwrap();
+            let mut console = create_console(filename);
+            let log_path = subopts.value_of("LOG").unwrap();
+            let mut log = String::new();
+            let result = File::open(log_path)
+                .and_then(|mut file| file.read_to_string(&mut log));
+            if let Err(e) = result {
+                println!("{}: {}", log_path, e);
+                process::exit(1);
+            }
+            match debug::nestest::run(&mut console, &log) {
+                Ok(lines) => println!("nestest: all {} lines match", lines),
+                Err(failure) => {
+                    println!("{}", failure);
+                    process::exit(1);
+                },
+            }
+        },
         _ => unreachable!(),
     }
 }
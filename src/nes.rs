@@ -1,12 +1,40 @@
+use std::any::Any;
+
 use cpu::{Cpu, Instruction};
+use input::{ButtonState, InputDevice, Port};
 use interconnect::Interconnect;
-use ppu::Ppu;
+use ppu::{Palette, Ppu};
 use rom::Cartridge;
 
+/// Ticks of the NTSC master clock per CPU cycle. The PPU
+/// runs at a third of that, 4 master cycles per dot.
+pub const MASTER_CYCLES_PER_CPU_CYCLE: u64 = 12;
+
+/// Why a run of the console came to an end.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Stop {
+    FrameCompleted,
+    CyclesElapsed,
+    Breakpoint,
+}
+
+/// What happened while running the console. Runs only stop
+/// between instructions, so may go a few cycles over.
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub struct RunResult {
+    pub stop: Stop,
+    /// CPU cycles run, including any DMA.
+    pub cycles: u64,
+    /// Frames the PPU completed.
+    pub frames: u64,
+    /// Audio samples produced, ready for `drain_audio`.
+    pub audio_samples: usize,
+}
+
 pub struct Nes {
     cpu: Cpu,
-    ppu: Ppu,
     interconnect: Interconnect,
+    master_cycles: u64,
 }
 
 impl Nes {
@@ -14,10 +42,11 @@ impl Nes {
         let mut interconnect = Interconnect::new(cartridge);
         let mut cpu = Cpu::new();
         cpu.reset(&mut interconnect);
+        let master_cycles = interconnect.cycles() * MASTER_CYCLES_PER_CPU_CYCLE;
         Nes {
-            cpu: cpu,
-            ppu: Ppu::new(),
-            interconnect: interconnect,
+            cpu,
+            interconnect,
+            master_cycles,
         }
     }
 
@@ -25,8 +54,74 @@ impl Nes {
         &self.cpu
     }
 
+    pub fn cpu_mut(&mut self) -> &mut Cpu {
+        &mut self.cpu
+    }
+
+    /// Ticks of the master clock since power up.
+    pub fn master_cycles(&self) -> u64 {
+        self.master_cycles
+    }
+
     pub fn ppu(&self) -> &Ppu {
-        &self.ppu
+        self.interconnect.ppu()
+    }
+
+    /// The latest complete frame, 256x240 palette indices
+    /// row by row. See `Palette` for converting them to colours.
+    pub fn frame(&self) -> &[u16] {
+        self.ppu().frame()
+    }
+
+    /// The latest complete frame as 3 bytes of RGB per pixel.
+    pub fn frame_rgb(&self) -> Vec<u8> {
+        self.palette().to_rgb(self.frame())
+    }
+
+    /// The latest complete frame as 4 bytes of RGBA per pixel.
+    pub fn frame_rgba(&self) -> Vec<u8> {
+        self.palette().to_rgba(self.frame())
+    }
+
+    pub fn palette(&self) -> &Palette {
+        self.interconnect.palette()
+    }
+
+    /// Change the palette used to convert frames to colours,
+    /// which is also what light guns see.
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.interconnect.set_palette(palette);
+    }
+
+    /// Hold down the buttons of the pad plugged into `port`.
+    /// Does nothing if another kind of device is plugged in.
+    pub fn set_buttons(&mut self, port: Port, buttons: ButtonState) {
+        self.interconnect.set_buttons(port, buttons);
+    }
+
+    /// Plug a device into a port, standard pads being plugged in to start with.
+    pub fn plug(&mut self, port: Port, device: Box<dyn InputDevice>) {
+        self.interconnect.plug(port, device);
+    }
+
+    /// The device plugged into a port, if it is a `T`.
+    pub fn device_mut<T: Any>(&mut self, port: Port) -> Option<&mut T> {
+        self.interconnect.device_mut(port)
+    }
+
+    /// Move the audio output so far onto the end of `out`, as samples
+    /// from -1 to 1 at the sample rate. Up to a second is kept.
+    pub fn drain_audio(&mut self, out: &mut Vec<f32>) {
+        self.interconnect.apu_mut().drain_audio(out);
+    }
+
+    pub fn sample_rate(&self) -> u32 {
+        self.interconnect.apu().sample_rate()
+    }
+
+    /// Change the rate audio is output at, 44.1kHz to start with.
+    pub fn set_sample_rate(&mut self, sample_rate: u32) {
+        self.interconnect.apu_mut().set_sample_rate(sample_rate);
     }
 
     pub fn interconnect(&mut self) -> &mut Interconnect {
@@ -54,32 +149,227 @@ impl Nes {
     }
 
     pub fn step(&mut self) {
-        // When the DMA register write occurs the interconnect
-        // automatically copies the 256 Sprite attribute data
-        // into SPR RAM on the PPU.
-        // At this point this function will have already been
-        // performed and we just need to simulate the 512 cycles
-        // that the CPU is stalled for.
-        let cpu_cycles = if self.interconnect.dma() {
-            self.interconnect.set_dma(false);
-            512
-        } else {
-            self.cpu.step(&mut self.interconnect)
-        };
+        let start = self.interconnect.cycles();
+        // The interconnect has already found any rising edge of the
+        // NMI line, so the CPU is handed it as a fresh low to high.
+        let nmi = self.interconnect.nmi();
+        self.cpu.set_nmi(false);
+        self.cpu.set_nmi(nmi);
+        self.cpu.set_irq(self.interconnect.irq());
 
-        // PPU runs 3 cycles per CPU cycle so let it catch up
-        // FIXME: this is very inaccurate, ideally we would
-        // emulate the CPU and PPU to the microcode level and
-        // synchronise them on a per-cycle basis instead of
-        // having the PPU catch up with the CPU.
-        for _ in 0..cpu_cycles * 3 {
-            self.ppu.step(&mut self.interconnect);
+        // The CPU clocks the interconnect on every memory access,
+        // which keeps the PPU in step with it cycle by cycle.
+        self.cpu.step(&mut self.interconnect);
+
+        // A write to the DMA register halts the CPU while
+        // the interconnect copies the 256 bytes of sprite
+        // attribute data into SPR RAM on the PPU.
+        if self.interconnect.sprite_dma_pending() {
+            self.interconnect.sprite_dma();
         }
+
+        let cycles = self.interconnect.cycles() - start;
+        self.master_cycles += cycles * MASTER_CYCLES_PER_CPU_CYCLE;
     }
 
-    pub fn run(&mut self) {
+    /// Run instructions until `stop` returns why to stop, checking
+    /// it before each instruction with what has happened so far.
+    fn run_with<F>(&mut self, mut stop: F) -> RunResult
+        where F: FnMut(&Nes, &RunResult) -> Option<Stop>
+    {
+        let start = self.interconnect.cycles();
+        let samples = self.interconnect.apu().samples_available();
+        let mut frame = self.ppu().frame_count();
+        let mut result = RunResult {
+            stop: Stop::Breakpoint,
+            cycles: 0,
+            frames: 0,
+            audio_samples: 0,
+        };
         loop {
+            if let Some(reason) = stop(self, &result) {
+                result.stop = reason;
+                return result;
+            }
             self.step();
+            result.cycles = self.interconnect.cycles() - start;
+            result.audio_samples = self.interconnect.apu().samples_available().saturating_sub(samples);
+            if self.ppu().frame_count() != frame {
+                frame = self.ppu().frame_count();
+                result.frames += 1;
+            }
+        }
+    }
+
+    /// Run until the PPU finishes its current frame, after
+    /// which `frame()` holds the picture it produced.
+    pub fn run_frame(&mut self) -> RunResult {
+        self.run_with(|_, result| {
+            if result.frames > 0 { Some(Stop::FrameCompleted) } else { None }
+        })
+    }
+
+    /// Run for at least a number of CPU cycles.
+    pub fn run_cycles(&mut self, cycles: u64) -> RunResult {
+        self.run_with(|_, result| {
+            if result.cycles >= cycles { Some(Stop::CyclesElapsed) } else { None }
+        })
+    }
+
+    /// Run until `breakpoint` is true of the console,
+    /// which is checked before each instruction.
+    pub fn run_until<F>(&mut self, mut breakpoint: F) -> RunResult
+        where F: FnMut(&Nes) -> bool
+    {
+        self.run_with(|nes, _| {
+            if breakpoint(nes) { Some(Stop::Breakpoint) } else { None }
+        })
+    }
+
+    /// Run for a number of frames, or forever if there is no limit.
+    pub fn run(&mut self, frames: Option<u64>) {
+        match frames {
+            Some(frames) => {
+                for _ in 0..frames {
+                    self.run_frame();
+                }
+            },
+            None => loop {
+                self.step();
+            },
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rom::{Cartridge, Mirroring};
+    use super::*;
+
+    #[test]
+    fn test_run_frame() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        nes.run_frame();
+        let result = nes.run_frame();
+        assert_eq!(result.stop, Stop::FrameCompleted);
+        assert_eq!(result.frames, 1);
+        // About 735 samples at 44.1kHz in a 60th of a second.
+        assert!((730..740).contains(&result.audio_samples));
+        let mut samples = Vec::new();
+        nes.drain_audio(&mut samples);
+        assert!(samples.len() >= result.audio_samples);
+        // 341 * 262 dots with rendering off, 3 dots a cycle, give or
+        // take the 7 cycle instructions either side of the frame.
+        assert!((29774..29788).contains(&result.cycles));
+    }
+
+    #[test]
+    fn test_run_cycles() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        let master_cycles = nes.master_cycles();
+        let result = nes.run_cycles(100_000);
+        assert_eq!(result.stop, Stop::CyclesElapsed);
+        assert!((100_000..100_010).contains(&result.cycles));
+        assert_eq!(result.frames, 3);
+        assert_eq!(nes.master_cycles() - master_cycles,
+                   result.cycles * MASTER_CYCLES_PER_CPU_CYCLE);
+    }
+
+    #[test]
+    fn test_run_until() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        let result = nes.run_until(|nes| nes.cpu().cycles() >= 1000);
+        assert_eq!(result.stop, Stop::Breakpoint);
+        assert!(nes.cpu().cycles() >= 1000);
+        assert_eq!(result.frames, 0);
+    }
+
+    #[test]
+    fn test_dmc_fetch_stalls_cpu() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        let stalled = |nes: &mut Nes| nes.interconnect().cycles() - nes.cpu().cycles();
+        let before = stalled(&mut nes);
+        nes.interconnect().cpu_write(0x4015, 0x10);
+        nes.step();
+        assert!((1..=4).contains(&(stalled(&mut nes) - before)));
+        // The one byte sample has been read.
+        assert!(!nes.interconnect().apu().dmc().active());
+    }
+
+    /// A cartridge with the given code at 0xc000, which is where
+    /// it resets to, and the NMI handler at 0xc010.
+    fn program(code: &[u8], nmi: &[u8]) -> Cartridge {
+        let mut prg = vec![0; 0x4000];
+        prg[..code.len()].copy_from_slice(code);
+        prg[0x10..0x10 + nmi.len()].copy_from_slice(nmi);
+        prg[0x3ffa..].copy_from_slice(&[0x10, 0xc0, 0x00, 0xc0, 0x00, 0xc0]);
+        Cartridge::with_prg(&prg)
+    }
+
+    #[test]
+    fn test_nmi_retriggers_during_vblank() {
+        // JMP $c000
+        let code = [0x4c, 0x00, 0xc0];
+        // STX $2000; STA $2000; JMP $c016
+        let nmi = [0x8e, 0x00, 0x20, 0x8d, 0x00, 0x20, 0x4c, 0x16, 0xc0];
+        let mut nes = Nes::new(program(&code, &nmi));
+        nes.cpu_mut().reg.a = 0x80;
+        nes.cpu_mut().reg.x = 0x00;
+        nes.interconnect().cpu_write(0x2000, 0x80);
+
+        let result = nes.run_until(|nes| nes.cpu().reg.pc == 0xc010);
+        assert_eq!(result.frames, 0);
+        assert_eq!(nes.ppu().scanline(), 241);
+        // Disabling and enabling NMIs with no instruction in
+        // between still gives the CPU a rising edge.
+        nes.step();
+        nes.step();
+        assert_eq!(nes.cpu().reg.pc, 0xc016);
+        nes.step();
+        assert_eq!(nes.cpu().reg.pc, 0xc010);
+    }
+
+    #[test]
+    fn test_sprite_dma_starts_at_sprite_address() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        for i in 0..256 {
+            nes.interconnect().cpu_write(0x0200 + i, i as u8);
+        }
+        nes.interconnect().cpu_write(0x2003, 0x10);
+        nes.interconnect().cpu_write(0x4014, 0x02);
+        nes.interconnect().sprite_dma();
+        // The copy wraps around, leaving the address where it began.
+        let mut read_spr = |addr: u8| {
+            nes.interconnect().cpu_write(0x2003, addr);
+            nes.interconnect().cpu_read(0x2004)
+        };
+        assert_eq!(read_spr(0x10), 0x00);
+        assert_eq!(read_spr(0xff), 0xef);
+        assert_eq!(read_spr(0x00), 0xf0);
+        assert_eq!(read_spr(0x0f), 0xff);
+    }
+
+    #[test]
+    fn test_sprite_dma_from_io_page() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        nes.interconnect().cpu_write(0x401f, 0x12);
+        nes.interconnect().cpu_write(0x4014, 0x40);
+        assert!((513..=514).contains(&nes.interconnect().sprite_dma()));
+        // The test mode registers at the end of the page are open bus.
+        nes.interconnect().cpu_write(0x0000, 0x34);
+        assert_eq!(nes.interconnect().cpu_read(0x401a), 0x34);
+    }
+
+    #[test]
+    fn test_write_only_apu_registers_are_open_bus() {
+        let mut nes = Nes::new(Cartridge::blank(Mirroring::Horizontal));
+        for addr in 0x4000..0x4014 {
+            nes.interconnect().cpu_write(addr, 0x00);
+            nes.interconnect().cpu_write(0x0000, 0x5a);
+            assert_eq!(nes.interconnect().cpu_read(addr), 0x5a);
         }
+        // Writing the DMA register would start a transfer.
+        nes.interconnect().cpu_write(0x0000, 0xa5);
+        assert_eq!(nes.interconnect().cpu_read(0x4014), 0xa5);
     }
 }
@@ -1,25 +1,21 @@
+use super::super::rom::Cartridge;
+
+#[derive(Default)]
 struct StatusFlags {
     vblank: bool,
+    sprite_zero_hit: bool,
+    sprite_overflow: bool,
     writes: bool,
 }
 
-impl Default for StatusFlags {
-    fn default() -> Self {
-        StatusFlags {
-            vblank: false,
-            writes: false,
-        }
-    }
-}
-
-struct Control {
-    name_table_address: u16,
-    addr_inc: u16,
-    sprite_pattern_table: u16,
-    background_pattern_table: u16,
-    sprite_x: u16,
-    sprite_y: u16,
-    nmi: bool,
+pub struct Control {
+    pub name_table_address: u16,
+    pub addr_inc: u16,
+    pub sprite_pattern_table: u16,
+    pub background_pattern_table: u16,
+    pub sprite_x: u16,
+    pub sprite_y: u16,
+    pub nmi: bool,
 }
 
 impl Control {
@@ -53,21 +49,114 @@ impl Default for Control {
     }
 }
 
-enum ColourMode {
+/// The PPU's internal scroll registers, which double as
+/// the VRAM address used by 0x2007, known as loopy's registers:
+/// http://wiki.nesdev.com/w/index.php/PPU_scrolling
+///
+/// Both addresses are laid out as the scroll position,
+/// 0yyy NNYY YYYX XXXX: fine Y, nametable, coarse Y and coarse X.
+#[derive(Default)]
+pub struct Scroll {
+    /// The current VRAM address.
+    pub v: u16,
+    /// The temporary VRAM address, the top left of the screen.
+    pub t: u16,
+    /// Fine X scroll.
+    pub x: u8,
+    /// The write toggle shared by 0x2005 and 0x2006,
+    /// clear for the first write and set for the second.
+    pub w: bool,
+}
+
+impl Scroll {
+    /// Set the nametable bits from a write to 0x2000.
+    fn write_control(&mut self, x: u8) {
+        self.t = (self.t & !0x0c00) | ((x as u16 & 0b11) << 10);
+    }
+
+    /// A write to 0x2005 sets the X scroll then the Y scroll.
+    fn write_scroll(&mut self, x: u8) {
+        if !self.w {
+            self.t = (self.t & !0x001f) | (x as u16 >> 3);
+            self.x = x & 0b111;
+        } else {
+            self.t = (self.t & !0x73e0) | ((x as u16 & 0b111) << 12) | ((x as u16 >> 3) << 5);
+        }
+        self.w = !self.w;
+    }
+
+    /// A write to 0x2006 sets the high then the low byte of the
+    /// address, only copying it to `v` once complete.
+    fn write_addr(&mut self, x: u8) {
+        if !self.w {
+            // Bit 14 is cleared along with the
+            // high byte, which has only 6 bits.
+            self.t = (self.t & 0x00ff) | ((x as u16 & 0x3f) << 8);
+        } else {
+            self.t = (self.t & 0xff00) | x as u16;
+            self.v = self.t;
+        }
+        self.w = !self.w;
+    }
+
+    /// Move to the next tile across, wrapping into
+    /// the horizontally adjacent nametable.
+    pub fn increment_x(&mut self) {
+        if self.v & 0x001f == 31 {
+            self.v &= !0x001f;
+            self.v ^= 0x0400;
+        } else {
+            self.v += 1;
+        }
+    }
+
+    /// Move down a line, wrapping into the vertically adjacent
+    /// nametable after the 30th row of tiles. Rows 30 and 31 hold
+    /// the attributes and only wrap within the same nametable.
+    pub fn increment_y(&mut self) {
+        if self.v & 0x7000 != 0x7000 {
+            self.v += 0x1000;
+        } else {
+            self.v &= !0x7000;
+            let mut y = (self.v & 0x03e0) >> 5;
+            if y == 29 {
+                y = 0;
+                self.v ^= 0x0800;
+            } else if y == 31 {
+                y = 0;
+            } else {
+                y += 1;
+            }
+            self.v = (self.v & !0x03e0) | (y << 5);
+        }
+    }
+
+    /// Reset the horizontal position to the left of the screen.
+    pub fn copy_x(&mut self) {
+        self.v = (self.v & !0x041f) | (self.t & 0x041f);
+    }
+
+    /// Reset the vertical position to the top of the screen.
+    pub fn copy_y(&mut self) {
+        self.v = (self.v & !0x7be0) | (self.t & 0x7be0);
+    }
+}
+
+pub enum ColourMode {
     Colour,
     Monochrome,
 }
 
 // https://wiki.nesdev.com/w/index.php/PPU_registers#PPUMASK
-struct Mask {
-    red: bool,
-    green: bool,
-    blue: bool,
-    sprites: bool,
-    background: bool,
-    left_sprites: bool,
-    left_background: bool,
-    colour_mode: ColourMode,
+pub struct Mask {
+    pub red: bool,
+    pub green: bool,
+    pub blue: bool,
+    pub sprites: bool,
+    pub background: bool,
+    pub left_sprites: bool,
+    pub left_background: bool,
+    pub colour_mode: ColourMode,
 }
 
 impl Mask {
@@ -107,7 +196,13 @@ impl Default for Mask {
 /// Allowing components to communicate without
 /// having a direct reference to the PPU struct.
 pub struct PpuInterface {
-    mem: Vec<u8>,
+    // Nametable RAM, 2K on the console itself with room
+    // for the 2K more four-screen cartridges provide.
+    nametables: Vec<u8>,
+    palette: [u8; 32],
+    // Reads through 0x2007 return the contents of this buffer,
+    // which is then filled with the byte at the address read.
+    read_buffer: u8,
     // Sprite Attribute RAM
     spr_ram: Vec<u8>,
     // The address in spr_ram that will be written
@@ -116,19 +211,32 @@ pub struct PpuInterface {
     control: Control,
     mask: Mask,
     flags: StatusFlags,
-    addr: u16,
+    scroll: Scroll,
+    // The PPU's data bus holds on to the last value written
+    // to or read from a register, which is what reads of the
+    // write-only registers return.
+    latch: u8,
+}
+
+impl Default for PpuInterface {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl PpuInterface {
     pub fn new() -> Self {
         PpuInterface {
-            mem: vec![0; 0x4000],
+            nametables: vec![0; 0x1000],
+            palette: [0; 32],
+            read_buffer: 0,
             spr_ram: vec![0; 256],
             spr_addr: 0x00,
             control: Default::default(),
             mask: Mask::default(),
             flags: Default::default(),
-            addr: 0,
+            scroll: Default::default(),
+            latch: 0,
         }
     }
 
@@ -137,60 +245,129 @@ impl PpuInterface {
         // TODO: incorporate more flags here
         let sr =
             (self.flags.vblank as u8) << 7 |
-            (self.flags.writes as u8) << 4;
+            (self.flags.sprite_zero_hit as u8) << 6 |
+            (self.flags.sprite_overflow as u8) << 5 |
+            (self.flags.writes as u8) << 4 |
+            (self.latch & 0x1f);
         self.flags.vblank = false;
+        self.scroll.w = false;
         sr
     }
 
-    pub fn read_register(&mut self, addr: u16) -> u8 {
+    pub fn read_register(&mut self, cartridge: &mut Cartridge, addr: u16) -> u8 {
+        let x = self.read_register_unlatched(cartridge, addr);
+        self.latch = x;
+        x
+    }
+
+    fn read_register_unlatched(&mut self, cartridge: &mut Cartridge, addr: u16) -> u8 {
         let addr = 0x2000 + (addr % 8);
 
         match addr {
-            0x2000 | 0x2001 => panic!("Trying to read from write-only PPU register: {:04x}", addr),
+            // Write-only registers
+            0x2000 | 0x2001 | 0x2003 | 0x2005 | 0x2006 => self.latch,
             0x2002 => self.read_status_register(),
-            0x2003 ... 0x2006 => panic!("Trying to read from write-only PPU register: {:04x}", addr),
+            // Reading sprite memory does not move the address on.
+            0x2004 => self.spr_ram[self.spr_addr as usize],
             0x2007 => {
-                // TODO: first read is invalid, only second
-                // read returns the requested data as it is
-                // buffered.
-                let x = self.read(self.addr);
-                self.addr += self.control.addr_inc;
+                let ppu_addr = self.scroll.v;
+                let buffered = self.read_buffer;
+                // Palette reads are not delayed, but the buffer is
+                // still filled from the nametable underneath.
+                let x = if ppu_addr % 0x4000 >= 0x3f00 {
+                    self.read_buffer = self.fetch(cartridge, ppu_addr - 0x1000);
+                    self.read_palette(ppu_addr)
+                } else {
+                    self.read_buffer = self.fetch(cartridge, ppu_addr);
+                    buffered
+                };
+                self.increment_addr();
                 x
             },
             _ => panic!("Invalid PPU register read address: {:04x}", addr),
         }
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
-        let addr = (addr % 0x4000) as usize;
-        self.mem[addr]
+    /// Read from the PPU's address space: pattern tables on the
+    /// cartridge, nametables in mirrored RAM and the palette.
+    pub fn read(&self, cartridge: &Cartridge, addr: u16) -> u8 {
+        match addr % 0x4000 {
+            0x0000 ..= 0x1fff => cartridge.ppu_read(addr % 0x4000),
+            addr @ 0x2000 ..= 0x3eff => {
+                self.nametables[cartridge.mirroring().nametable_offset(addr)]
+            },
+            addr => self.read_palette(addr),
+        }
+    }
+
+    /// Read with the address going out on the PPU's bus,
+    /// where the cartridge's mapper can see it.
+    pub fn fetch(&self, cartridge: &mut Cartridge, addr: u16) -> u8 {
+        cartridge.ppu_address(addr);
+        self.read(cartridge, addr)
     }
 
-    pub fn write_register(&mut self, addr: u16, x: u8) {
+    /// The palette holds 32 entries, but the transparent colour
+    /// of each sprite palette is shared with the background.
+    fn palette_index(addr: u16) -> usize {
+        let index = (addr % 0x20) as usize;
+        if index >= 0x10 && index & 0b11 == 0 {
+            index - 0x10
+        } else {
+            index
+        }
+    }
+
+    #[inline(always)]
+    pub fn read_palette(&self, addr: u16) -> u8 {
+        self.palette[Self::palette_index(addr)]
+    }
+
+    pub fn write_register(&mut self, cartridge: &mut Cartridge, addr: u16, x: u8) {
         let addr = 0x2000 + (addr % 8);
+        self.latch = x;
 
         match addr {
-            0x2000 => self.control.apply(x),
+            0x2000 => {
+                self.control.apply(x);
+                self.scroll.write_control(x);
+            },
             0x2001 => self.mask.apply(x),
-            0x2002 => panic!("Trying to write to read-only PPU status register: {:04x}", addr),
+            // Writing to the read-only status register only fills the latch.
+            0x2002 => {},
             0x2003 => self.spr_addr = x,
-            0x2004 => self.spr_ram[self.spr_addr as usize] = x,
+            0x2004 => self.write_spr_data(x),
+            0x2005 => self.scroll.write_scroll(x),
             0x2006 => {
-                // Write the lower nybble of the PPU address
-                // to be read from or written to with 0x2007.
-                self.addr = (self.addr << 8) | (x as u16);
+                self.scroll.write_addr(x);
+                // The second write puts the new address on the bus.
+                if !self.scroll.w {
+                    cartridge.ppu_address(self.scroll.v);
+                }
             },
             0x2007 => {
-                let ppu_addr = self.addr;
-                self.write(ppu_addr, x)
+                let ppu_addr = self.scroll.v;
+                cartridge.ppu_address(ppu_addr);
+                self.write(cartridge, ppu_addr, x);
+                self.increment_addr();
             },
             _ => panic!("Invalid PPU register write address: {:#x}", addr),
         }
     }
 
-    pub fn write(&mut self, addr: u16, x: u8) {
-        let addr = (addr % 0x4000) as usize;
-        self.mem[addr] = x;
+    /// Move the VRAM address on after an access through 0x2007.
+    fn increment_addr(&mut self) {
+        self.scroll.v = self.scroll.v.wrapping_add(self.control.addr_inc) & 0x7fff;
+    }
+
+    pub fn write(&mut self, cartridge: &mut Cartridge, addr: u16, x: u8) {
+        match addr % 0x4000 {
+            0x0000 ..= 0x1fff => cartridge.ppu_write(addr % 0x4000, x),
+            addr @ 0x2000 ..= 0x3eff => {
+                self.nametables[cartridge.mirroring().nametable_offset(addr)] = x;
+            },
+            addr => self.palette[Self::palette_index(addr)] = x & 0x3f,
+        }
     }
 
     #[inline(always)]
@@ -198,9 +375,236 @@ impl PpuInterface {
         self.spr_ram[addr as usize] = x;
     }
 
+    /// Write to sprite memory at the address set through 0x2003,
+    /// moving it on to the next byte, as 0x2004 and DMA do.
+    pub fn write_spr_data(&mut self, x: u8) {
+        self.spr_ram[self.spr_addr as usize] = x;
+        self.spr_addr = self.spr_addr.wrapping_add(1);
+    }
+
+    #[inline(always)]
+    pub fn read_spr(&self, addr: u8) -> u8 {
+        self.spr_ram[addr as usize]
+    }
+
+    pub fn set_vblank(&mut self, value: bool) {
+        self.flags.vblank = value;
+    }
+
+    pub fn set_sprite_zero_hit(&mut self, value: bool) {
+        self.flags.sprite_zero_hit = value;
+    }
+
+    pub fn set_sprite_overflow(&mut self, value: bool) {
+        self.flags.sprite_overflow = value;
+    }
+
+    /// The level of the PPU's NMI output, which is held high for as
+    /// long as vblank is set and NMIs are enabled in the control register.
+    #[inline(always)]
+    pub fn nmi(&self) -> bool {
+        self.control.nmi && self.flags.vblank
+    }
+
     #[inline(always)]
     pub fn control(&self) -> &Control { &self.control }
 
     #[inline(always)]
     pub fn mask(&self) -> &Mask { &self.mask }
+
+    #[inline(always)]
+    pub fn scroll(&self) -> &Scroll { &self.scroll }
+
+    #[inline(always)]
+    pub fn scroll_mut(&mut self) -> &mut Scroll { &mut self.scroll }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::PpuInterface;
+    use super::super::super::rom::{Cartridge, Mirroring};
+
+    // The addresses are grouped by field as yyy NN YYYYY XXXXX.
+    #[allow(clippy::unusual_byte_groupings)]
+    #[test]
+    fn test_scroll_writes() {
+        // The example from the nesdev wiki's PPU scrolling page.
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write_register(&mut cartridge, 0x2000, 0b00000000);
+        interface.read_register(&mut cartridge, 0x2002);
+        assert!(!interface.scroll().w);
+
+        interface.write_register(&mut cartridge, 0x2005, 0b01111101);
+        assert_eq!(interface.scroll().t, 0b000_00_00000_01111);
+        assert_eq!(interface.scroll().x, 0b101);
+        assert!(interface.scroll().w);
+
+        interface.write_register(&mut cartridge, 0x2005, 0b01011110);
+        assert_eq!(interface.scroll().t, 0b110_00_01011_01111);
+        assert!(!interface.scroll().w);
+
+        interface.write_register(&mut cartridge, 0x2006, 0b00111101);
+        assert_eq!(interface.scroll().t, 0b011_11_01011_01111);
+        interface.write_register(&mut cartridge, 0x2006, 0b11110000);
+        assert_eq!(interface.scroll().t, 0b011_11_01111_10000);
+        assert_eq!(interface.scroll().v, interface.scroll().t);
+    }
+
+    #[test]
+    fn test_status_read_resets_toggle() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write_register(&mut cartridge, 0x2006, 0x21);
+        interface.read_register(&mut cartridge, 0x2002);
+        interface.write_register(&mut cartridge, 0x2006, 0x23);
+        interface.write_register(&mut cartridge, 0x2006, 0x45);
+        assert_eq!(interface.scroll().v, 0x2345);
+    }
+
+    #[test]
+    fn test_data_increments_address() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write_register(&mut cartridge, 0x2006, 0x20);
+        interface.write_register(&mut cartridge, 0x2006, 0x00);
+        interface.write_register(&mut cartridge, 0x2007, 0x11);
+        interface.write_register(&mut cartridge, 0x2007, 0x22);
+        assert_eq!(interface.scroll().v, 0x2002);
+        assert_eq!((interface.read(&cartridge, 0x2000), interface.read(&cartridge, 0x2001)), (0x11, 0x22));
+
+        // Increment by 32 to go down a column.
+        interface.write_register(&mut cartridge, 0x2000, 0b100);
+        interface.write_register(&mut cartridge, 0x2007, 0x33);
+        assert_eq!(interface.scroll().v, 0x2022);
+    }
+
+    #[test]
+    fn test_scroll_increments() {
+        let mut interface = PpuInterface::new();
+        let scroll = interface.scroll_mut();
+        scroll.v = 0x001f;
+        scroll.increment_x();
+        assert_eq!(scroll.v, 0x0400);
+
+        // Fine Y 7 on coarse Y 29 wraps to the next nametable down.
+        scroll.v = 0x7000 | (29 << 5);
+        scroll.increment_y();
+        assert_eq!(scroll.v, 0x0800);
+        // But coarse Y 31 wraps within the same one.
+        scroll.v = 0x7000 | (31 << 5);
+        scroll.increment_y();
+        assert_eq!(scroll.v, 0x0000);
+
+        scroll.t = 0x041f;
+        scroll.v = 0x7be0;
+        scroll.copy_x();
+        assert_eq!(scroll.v, 0x7fff);
+        scroll.t = 0x0000;
+        scroll.copy_y();
+        assert_eq!(scroll.v, 0x041f);
+    }
+
+    #[test]
+    fn test_buffered_data_reads() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write(&mut cartridge, 0x2000, 0x11);
+        interface.write(&mut cartridge, 0x2001, 0x22);
+        interface.write_register(&mut cartridge, 0x2006, 0x20);
+        interface.write_register(&mut cartridge, 0x2006, 0x00);
+        // The first read returns the stale contents of the buffer.
+        assert_eq!(interface.read_register(&mut cartridge, 0x2007), 0x00);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2007), 0x11);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2007), 0x22);
+    }
+
+    #[test]
+    fn test_palette_reads_are_not_buffered() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write(&mut cartridge, 0x2f05, 0x11);
+        interface.write(&mut cartridge, 0x3f05, 0x22);
+        interface.write_register(&mut cartridge, 0x2006, 0x3f);
+        interface.write_register(&mut cartridge, 0x2006, 0x05);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2007), 0x22);
+        // The buffer is filled from the nametable under the palette.
+        interface.write_register(&mut cartridge, 0x2006, 0x00);
+        interface.write_register(&mut cartridge, 0x2006, 0x00);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2007), 0x11);
+    }
+
+    #[test]
+    fn test_palette_mirrors() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        for (i, addr) in [0x3f10, 0x3f14, 0x3f18, 0x3f1c].iter().enumerate() {
+            interface.write(&mut cartridge, *addr, i as u8 + 1);
+            assert_eq!(interface.read_palette(addr - 0x10), i as u8 + 1);
+        }
+        interface.write(&mut cartridge, 0x3f11, 0x30);
+        assert_eq!(interface.read_palette(0x3f01), 0x00);
+        assert_eq!(interface.read(&cartridge, 0x3f31), 0x30);
+    }
+
+    #[test]
+    fn test_nametable_mirroring() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Vertical);
+        interface.write(&mut cartridge, 0x2005, 0x42);
+        assert_eq!(interface.read(&cartridge, 0x2805), 0x42);
+        assert_eq!(interface.read(&cartridge, 0x2405), 0x00);
+        // 0x3000-0x3eff mirrors the nametables.
+        assert_eq!(interface.read(&cartridge, 0x3005), 0x42);
+
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write(&mut cartridge, 0x2005, 0x42);
+        assert_eq!(interface.read(&cartridge, 0x2405), 0x42);
+    }
+
+    #[test]
+    fn test_pattern_tables_on_cartridge() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write_register(&mut cartridge, 0x2006, 0x12);
+        interface.write_register(&mut cartridge, 0x2006, 0x34);
+        interface.write_register(&mut cartridge, 0x2007, 0x56);
+        assert_eq!(cartridge.ppu_read(0x1234), 0x56);
+    }
+
+    #[test]
+    fn test_nmi_follows_control_during_vblank() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.set_vblank(true);
+        assert!(!interface.nmi());
+        interface.write_register(&mut cartridge, 0x2000, 0x80);
+        assert!(interface.nmi());
+        // Toggling NMIs off and on again raises it again.
+        interface.write_register(&mut cartridge, 0x2000, 0x00);
+        assert!(!interface.nmi());
+        interface.write_register(&mut cartridge, 0x2000, 0x80);
+        assert!(interface.nmi());
+        // Reading the status clears vblank, dropping it.
+        interface.read_register(&mut cartridge, 0x2002);
+        assert!(!interface.nmi());
+    }
+
+    #[test]
+    fn test_sprite_data_writes_increment_address() {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        interface.write_register(&mut cartridge, 0x2003, 0xfe);
+        for x in 1..=3 {
+            interface.write_register(&mut cartridge, 0x2004, x);
+        }
+        assert_eq!(interface.read_spr(0xfe), 1);
+        assert_eq!(interface.read_spr(0xff), 2);
+        assert_eq!(interface.read_spr(0x00), 3);
+
+        // Reads come from sprite memory and leave the address alone.
+        interface.write_register(&mut cartridge, 0x2003, 0xff);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2004), 2);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2004), 2);
+    }
 }
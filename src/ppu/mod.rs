@@ -1,5 +1,7 @@
 mod interface;
+mod palette;
 mod ppu;
 
 pub use self::interface::{PpuInterface};
-pub use self::ppu::{Ppu};
\ No newline at end of file
+pub use self::palette::{Palette, PaletteError};
+pub use self::ppu::{Ppu, WIDTH, HEIGHT};
\ No newline at end of file
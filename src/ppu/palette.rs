@@ -0,0 +1,216 @@
+//! Conversion of the PPU's palette indices to RGB colours:
+//! http://wiki.nesdev.com/w/index.php/PPU_palettes
+//!
+//! Palettes can also be loaded from `.pal` files, which hold 3 bytes
+//! of RGB for each of the 64 colours, optionally followed by the
+//! colours for each of the 7 combinations of emphasis bits.
+
+use std::error;
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::io::Read;
+use std::path::Path;
+use std::result;
+
+/// Number of colours the PPU can output before emphasis.
+pub const COLOURS: usize = 64;
+
+/// A commonly used approximation of the colours output by the 2C02.
+const NTSC_2C02: [u32; COLOURS] = [
+    0x7c7c7c, 0x0000fc, 0x0000bc, 0x4428bc, 0x940084, 0xa80020, 0xa81000, 0x881400,
+    0x503000, 0x007800, 0x006800, 0x005800, 0x004058, 0x000000, 0x000000, 0x000000,
+    0xbcbcbc, 0x0078f8, 0x0058f8, 0x6844fc, 0xd800cc, 0xe40058, 0xf83800, 0xe45c10,
+    0xac7c00, 0x00b800, 0x00a800, 0x00a844, 0x008888, 0x000000, 0x000000, 0x000000,
+    0xf8f8f8, 0x3cbcfc, 0x6888fc, 0x9878f8, 0xf878f8, 0xf85898, 0xf87858, 0xfca044,
+    0xf8b800, 0xb8f818, 0x58d854, 0x58f898, 0x00e8d8, 0x787878, 0x000000, 0x000000,
+    0xfcfcfc, 0xa4e4fc, 0xb8b8f8, 0xd8b8f8, 0xf8b8f8, 0xf8a4c0, 0xf0d0b0, 0xfce0a8,
+    0xf8d878, 0xd8f878, 0xb8f8b8, 0xb8f8d8, 0x00fcfc, 0xf8d8f8, 0x000000, 0x000000,
+];
+
+/// How much each emphasis bit darkens the channels it does not emphasise.
+const EMPHASIS_ATTENUATION: f32 = 0.75;
+
+/// Size of a `.pal` file with only the base colours.
+pub const BASE_SIZE: usize = COLOURS * 3;
+
+/// Size of a `.pal` file which includes the emphasised colours.
+pub const EMPHASIS_SIZE: usize = BASE_SIZE * 8;
+
+/// Maps the 9-bit palette indices in the PPU's frames, a colour
+/// with the red, green and blue emphasis bits above it, to RGB.
+pub struct Palette {
+    colours: Vec<[u8; 3]>,
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Self::ntsc()
+    }
+}
+
+impl Palette {
+    /// The built-in palette of the NTSC 2C02.
+    pub fn ntsc() -> Palette {
+        let base: Vec<[u8; 3]> = NTSC_2C02.iter()
+            .map(|&rgb| [(rgb >> 16) as u8, (rgb >> 8) as u8, rgb as u8])
+            .collect();
+        Palette::with_emphasis(&base)
+    }
+
+    /// Load a palette from the contents of a `.pal` file. Files with
+    /// only the 64 base colours have their emphasis variants derived.
+    pub fn from_bytes(data: &[u8]) -> Result<Palette> {
+        if data.len() != BASE_SIZE && data.len() != EMPHASIS_SIZE {
+            return Err(PaletteError::Size(data.len()));
+        }
+        let colours: Vec<[u8; 3]> = data.chunks(3)
+            .map(|rgb| [rgb[0], rgb[1], rgb[2]])
+            .collect();
+        if data.len() == BASE_SIZE {
+            Ok(Palette::with_emphasis(&colours))
+        } else {
+            Ok(Palette { colours })
+        }
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Palette> {
+        let mut file = File::open(path)?;
+        let mut buf = Vec::new();
+        file.read_to_end(&mut buf)?;
+        Palette::from_bytes(&buf)
+    }
+
+    /// Build a full palette from its 64 base colours, applying each
+    /// emphasis bit by darkening the other two channels.
+    fn with_emphasis(base: &[[u8; 3]]) -> Palette {
+        let mut colours = Vec::with_capacity(COLOURS * 8);
+        for emphasis in 0..8u8 {
+            for colour in base {
+                let mut rgb = *colour;
+                for (channel, value) in rgb.iter_mut().enumerate() {
+                    let others = emphasis & !(1 << channel);
+                    for _ in 0..others.count_ones() {
+                        *value = (*value as f32 * EMPHASIS_ATTENUATION) as u8;
+                    }
+                }
+                colours.push(rgb);
+            }
+        }
+        Palette { colours }
+    }
+
+    /// The colour of a palette index from a frame.
+    #[inline(always)]
+    pub fn rgb(&self, index: u16) -> [u8; 3] {
+        self.colours[(index as usize) % self.colours.len()]
+    }
+
+    /// Convert a frame to 3 bytes of RGB per pixel.
+    pub fn to_rgb(&self, frame: &[u16]) -> Vec<u8> {
+        frame.iter().flat_map(|&index| self.rgb(index)).collect()
+    }
+
+    /// Convert a frame to 4 bytes of RGBA per pixel, all opaque.
+    pub fn to_rgba(&self, frame: &[u16]) -> Vec<u8> {
+        let mut rgba = Vec::with_capacity(frame.len() * 4);
+        for &index in frame {
+            let [r, g, b] = self.rgb(index);
+            rgba.extend_from_slice(&[r, g, b, 0xff]);
+        }
+        rgba
+    }
+}
+
+pub type Result<T> = result::Result<T, PaletteError>;
+
+#[derive(Debug)]
+pub enum PaletteError {
+    Io(io::Error),
+    /// The file was neither 192 nor 1536 bytes long.
+    Size(usize),
+}
+
+impl fmt::Display for PaletteError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            PaletteError::Io(ref err) => write!(f, "IO error: {}", err),
+            PaletteError::Size(size) => write!(f, "Palette is {} bytes, expected {} or {}",
+                                               size, BASE_SIZE, EMPHASIS_SIZE),
+        }
+    }
+}
+
+impl error::Error for PaletteError {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            PaletteError::Io(ref err) => Some(err),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for PaletteError {
+    fn from(err: io::Error) -> PaletteError {
+        PaletteError::Io(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ntsc_colours() {
+        let palette = Palette::ntsc();
+        assert_eq!(palette.rgb(0x0f), [0x00, 0x00, 0x00]);
+        assert_eq!(palette.rgb(0x16), [0xf8, 0x38, 0x00]);
+        assert_eq!(palette.rgb(0x30), [0xfc, 0xfc, 0xfc]);
+    }
+
+    #[test]
+    fn test_emphasis() {
+        let palette = Palette::ntsc();
+        // Red emphasis darkens green and blue.
+        assert_eq!(palette.rgb(0x40 | 0x30), [0xfc, 0xbd, 0xbd]);
+        // Red and green together darken blue twice over.
+        assert_eq!(palette.rgb(0xc0 | 0x30), [0xbd, 0xbd, 0x8d]);
+        assert_eq!(palette.rgb(0x1c0 | 0x30), [0x8d, 0x8d, 0x8d]);
+    }
+
+    #[test]
+    fn test_frame_conversion() {
+        let palette = Palette::ntsc();
+        let frame = [0x0f, 0x30];
+        assert_eq!(palette.to_rgb(&frame), vec![0x00, 0x00, 0x00, 0xfc, 0xfc, 0xfc]);
+        assert_eq!(palette.to_rgba(&frame), vec![0x00, 0x00, 0x00, 0xff, 0xfc, 0xfc, 0xfc, 0xff]);
+    }
+
+    #[test]
+    fn test_load_base_colours() {
+        let data: Vec<u8> = (0..BASE_SIZE).map(|i| i as u8).collect();
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.rgb(0x01), [3, 4, 5]);
+        // Emphasis is derived from the base colours.
+        assert_eq!(palette.rgb(0x40 | 0x01), [3, 3, 3]);
+    }
+
+    #[test]
+    fn test_load_emphasis_colours() {
+        let data: Vec<u8> = (0..EMPHASIS_SIZE).map(|i| (i / BASE_SIZE) as u8).collect();
+        let palette = Palette::from_bytes(&data).unwrap();
+        assert_eq!(palette.rgb(0x3f), [0, 0, 0]);
+        assert_eq!(palette.rgb(0x40), [1, 1, 1]);
+        assert_eq!(palette.rgb(0x1c0 | 0x12), [7, 7, 7]);
+    }
+
+    #[test]
+    fn test_load_bad_size() {
+        match Palette::from_bytes(&[0; 191]) {
+            Err(PaletteError::Size(191)) => (),
+            _ => panic!("expected a size error"),
+        }
+        assert!(Palette::from_bytes(&[]).is_err());
+        assert!(Palette::from_file("does-not-exist.pal").is_err());
+    }
+}
@@ -1,13 +1,712 @@
-use super::super::interconnect::Interconnect;
+//! The 2C02's rendering pipeline: http://wiki.nesdev.com/w/index.php/PPU_rendering
 
-pub struct Ppu;
+use super::super::rom::Cartridge;
+use super::interface::{ColourMode, PpuInterface};
+
+/// Width of the picture in pixels.
+pub const WIDTH: usize = 256;
+/// Height of the picture in pixels.
+pub const HEIGHT: usize = 240;
+
+/// Dots in a scanline and scanlines in a frame.
+const DOTS: u16 = 341;
+const SCANLINES: u16 = 262;
+/// The scanline before the first visible one, which fetches
+/// the first tiles of the frame without drawing anything.
+const PRE_RENDER_SCANLINE: u16 = 261;
+/// The scanline vblank starts on, after a scanline
+/// of doing nothing once the picture is complete.
+const VBLANK_SCANLINE: u16 = 241;
+
+/// Sprites drawn on a single scanline at most.
+const SPRITES_PER_LINE: usize = 8;
+
+/// Sprite attribute bits.
+const SPRITE_PALETTE: u8 = 0b00000011;
+const SPRITE_BEHIND_BACKGROUND: u8 = 0b00100000;
+const SPRITE_FLIP_HORIZONTAL: u8 = 0b01000000;
+const SPRITE_FLIP_VERTICAL: u8 = 0b10000000;
+
+/// The background tile being fetched, which is
+/// loaded into the shift registers once complete.
+#[derive(Default)]
+struct TileFetch {
+    name: u8,
+    attribute: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+/// A sprite found on the scanline, with its row of pattern
+/// data fetched and already flipped horizontally if need be.
+#[derive(Clone, Copy, Default)]
+struct Sprite {
+    x: u8,
+    attributes: u8,
+    pattern_lo: u8,
+    pattern_hi: u8,
+}
+
+pub struct Ppu {
+    scanline: u16,
+    dot: u16,
+    // Number of frames completed since power up.
+    frame_count: u64,
+    fetch: TileFetch,
+    // Background shift registers, the pixel being drawn is
+    // in the high bit and the next tile is loaded into the
+    // low byte every 8 dots.
+    pattern_lo: u16,
+    pattern_hi: u16,
+    attribute_lo: u16,
+    attribute_hi: u16,
+    // Secondary OAM, the sprites in range of the next scanline
+    // as found by sprite evaluation, 4 bytes each.
+    secondary_oam: [u8; SPRITES_PER_LINE * 4],
+    secondary_count: usize,
+    secondary_has_zero: bool,
+    // The sprites being drawn on the current scanline.
+    sprites: [Sprite; SPRITES_PER_LINE],
+    sprite_count: usize,
+    sprite_zero: bool,
+    // Colour of every pixel of the frame being drawn as an index
+    // into the NES palette, with the emphasis bits above it.
+    framebuffer: Vec<u16>,
+    // The last complete frame, swapped with the
+    // framebuffer once the picture is finished.
+    frame: Vec<u16>,
+}
+
+impl Default for Ppu {
+    fn default() -> Self {
+        Self::new()
+    }
+}
 
 impl Ppu {
     pub fn new() -> Self {
-        Ppu
+        Ppu {
+            scanline: 0,
+            dot: 0,
+            frame_count: 0,
+            fetch: Default::default(),
+            pattern_lo: 0,
+            pattern_hi: 0,
+            attribute_lo: 0,
+            attribute_hi: 0,
+            secondary_oam: [0xff; SPRITES_PER_LINE * 4],
+            secondary_count: 0,
+            secondary_has_zero: false,
+            sprites: [Sprite::default(); SPRITES_PER_LINE],
+            sprite_count: 0,
+            sprite_zero: false,
+            framebuffer: vec![0; WIDTH * HEIGHT],
+            frame: vec![0; WIDTH * HEIGHT],
+        }
+    }
+
+    /// The frame being drawn, as palette indices row by row.
+    pub fn framebuffer(&self) -> &[u16] {
+        &self.framebuffer
+    }
+
+    /// The pixels the beam has drawn most recently: the frame being
+    /// drawn, or the one just finished once it is swapped out at vblank.
+    pub fn beam_pixels(&self) -> &[u16] {
+        let swapped = self.scanline > VBLANK_SCANLINE
+            || (self.scanline == VBLANK_SCANLINE && self.dot > 1);
+        if swapped {
+            &self.frame
+        } else {
+            &self.framebuffer
+        }
+    }
+
+    /// The last complete frame, as palette indices row by row.
+    /// Each index is 9 bits, the emphasis bits of PPUMASK in
+    /// effect when the pixel was drawn above the colour.
+    pub fn frame(&self) -> &[u16] {
+        &self.frame
+    }
+
+    pub fn scanline(&self) -> u16 {
+        self.scanline
+    }
+
+    pub fn dot(&self) -> u16 {
+        self.dot
+    }
+
+    /// The number of frames completed since power up,
+    /// a frame ending once the pre-render scanline does.
+    pub fn frame_count(&self) -> u64 {
+        self.frame_count
+    }
+
+    /// Run the PPU for a single dot.
+    pub fn step(&mut self, interface: &mut PpuInterface, cartridge: &mut Cartridge) {
+        let rendering = interface.mask().background || interface.mask().sprites;
+        let fetching = self.scanline < HEIGHT as u16 || self.scanline == PRE_RENDER_SCANLINE;
+
+        if self.scanline == VBLANK_SCANLINE && self.dot == 1 {
+            interface.set_vblank(true);
+            ::std::mem::swap(&mut self.frame, &mut self.framebuffer);
+        }
+        if self.scanline == PRE_RENDER_SCANLINE && self.dot == 1 {
+            interface.set_vblank(false);
+            interface.set_sprite_zero_hit(false);
+            interface.set_sprite_overflow(false);
+        }
+        if rendering && fetching {
+            self.fetch_background(interface, cartridge);
+            if self.dot == 257 {
+                self.evaluate_sprites(interface);
+            }
+            if (257..=320).contains(&self.dot) {
+                self.fetch_sprite(interface, cartridge);
+            }
+        }
+        if self.scanline < HEIGHT as u16 && (1..=WIDTH as u16).contains(&self.dot) {
+            self.draw_pixel(interface);
+        }
+
+        self.dot += 1;
+        // With rendering enabled odd frames are a dot shorter,
+        // skipping the last dot of the pre-render scanline.
+        let odd_frame = self.frame_count % 2 == 1;
+        if rendering && odd_frame && self.scanline == PRE_RENDER_SCANLINE && self.dot == DOTS - 1 {
+            self.dot = DOTS;
+        }
+        if self.dot == DOTS {
+            self.dot = 0;
+            self.scanline = (self.scanline + 1) % SCANLINES;
+            if self.scanline == 0 {
+                self.frame_count += 1;
+            }
+        }
+    }
+
+    /// Run the background fetches and shift registers for the
+    /// current dot. Each tile takes 8 dots, fetching the nametable,
+    /// attribute and two pattern bytes in turn at the position in
+    /// `v`, which is then moved on to the next tile.
+    fn fetch_background(&mut self, interface: &mut PpuInterface, cartridge: &mut Cartridge) {
+        let dot = self.dot;
+        if (2..=257).contains(&dot) || (321..=337).contains(&dot) {
+            self.shift_background();
+            let v = interface.scroll().v;
+            match (dot - 1) % 8 {
+                0 => {
+                    self.load_background();
+                    self.fetch.name = interface.fetch(cartridge, 0x2000 | (v & 0x0fff));
+                },
+                2 => {
+                    let addr = 0x23c0 | (v & 0x0c00) | ((v >> 4) & 0x38) | ((v >> 2) & 0x07);
+                    // Each attribute byte covers a 4x4 tile area
+                    // with 2 bits for each 2x2 quadrant.
+                    let shift = ((v >> 4) & 0b100) | (v & 0b10);
+                    self.fetch.attribute = (interface.fetch(cartridge, addr) >> shift) & 0b11;
+                },
+                4 => self.fetch.pattern_lo = interface.fetch(cartridge, self.pattern_address(interface)),
+                6 => self.fetch.pattern_hi = interface.fetch(cartridge, self.pattern_address(interface) + 8),
+                7 => interface.scroll_mut().increment_x(),
+                _ => {},
+            }
+        }
+        match dot {
+            256 => interface.scroll_mut().increment_y(),
+            257 => interface.scroll_mut().copy_x(),
+            280 ..= 304 if self.scanline == PRE_RENDER_SCANLINE => {
+                interface.scroll_mut().copy_y();
+            },
+            _ => {},
+        }
+    }
+
+    fn pattern_address(&self, interface: &PpuInterface) -> u16 {
+        let fine_y = interface.scroll().v >> 12;
+        interface.control().background_pattern_table
+            + (self.fetch.name as u16) * 16
+            + fine_y
+    }
+
+    fn shift_background(&mut self) {
+        self.pattern_lo <<= 1;
+        self.pattern_hi <<= 1;
+        self.attribute_lo <<= 1;
+        self.attribute_hi <<= 1;
+    }
+
+    fn load_background(&mut self) {
+        let fetch = &self.fetch;
+        self.pattern_lo = (self.pattern_lo & 0xff00) | fetch.pattern_lo as u16;
+        self.pattern_hi = (self.pattern_hi & 0xff00) | fetch.pattern_hi as u16;
+        // The attribute applies to the whole tile, so its
+        // bits are spread across all 8 pixels.
+        let spread = |bit: u8| if fetch.attribute & bit != 0 { 0x00ff } else { 0x0000 };
+        self.attribute_lo = (self.attribute_lo & 0xff00) | spread(0b01);
+        self.attribute_hi = (self.attribute_hi & 0xff00) | spread(0b10);
+    }
+
+    /// The background pixel at the current dot as the palette
+    /// number in the upper two bits and the colour within it
+    /// in the lower two, a colour of zero being transparent.
+    fn background_pixel(&self, interface: &PpuInterface) -> u8 {
+        let x = self.dot - 1;
+        let mask = interface.mask();
+        if !mask.background || (x < 8 && !mask.left_background) {
+            return 0;
+        }
+        let fine_x = interface.scroll().x;
+        let bit = |register: u16| ((register >> (15 - fine_x)) & 1) as u8;
+        let colour = bit(self.pattern_hi) << 1 | bit(self.pattern_lo);
+        let palette = bit(self.attribute_hi) << 1 | bit(self.attribute_lo);
+        palette << 2 | colour
+    }
+
+    /// Find the sprites in range of the next scanline, copying
+    /// up to 8 of them into secondary OAM. Once it is full the
+    /// search for a ninth sprite, which sets the overflow flag,
+    /// is buggy: the offset into each sprite's attributes is
+    /// wrongly incremented along with the sprite being checked.
+    /// http://wiki.nesdev.com/w/index.php/PPU_sprite_evaluation
+    fn evaluate_sprites(&mut self, interface: &mut PpuInterface) {
+        self.secondary_oam = [0xff; SPRITES_PER_LINE * 4];
+        self.secondary_count = 0;
+        self.secondary_has_zero = false;
+        // Sprites are not drawn on the first scanline, so
+        // the pre-render scanline never finds any.
+        if self.scanline == PRE_RENDER_SCANLINE {
+            return;
+        }
+
+        let height = interface.control().sprite_y;
+        let scanline = self.scanline;
+        let in_range = |y: u8| {
+            let row = scanline as i32 - y as i32;
+            row >= 0 && row < height as i32
+        };
+        let mut n = 0;
+        while n < 64 && self.secondary_count < SPRITES_PER_LINE {
+            let addr = n * 4;
+            if in_range(interface.read_spr(addr as u8)) {
+                let slot = self.secondary_count * 4;
+                for i in 0..4 {
+                    self.secondary_oam[slot + i] = interface.read_spr((addr + i) as u8);
+                }
+                self.secondary_has_zero |= n == 0;
+                self.secondary_count += 1;
+            }
+            n += 1;
+        }
+        let mut m = 0;
+        while n < 64 {
+            if in_range(interface.read_spr((n * 4 + m) as u8)) {
+                interface.set_sprite_overflow(true);
+                break;
+            }
+            n += 1;
+            m = (m + 1) % 4;
+        }
+    }
+
+    /// Fetch the pattern data of the sprites in secondary OAM,
+    /// one every 8 dots. Empty slots still fetch tile $FF.
+    fn fetch_sprite(&mut self, interface: &PpuInterface, cartridge: &mut Cartridge) {
+        let offset = self.dot - 257;
+        let slot = (offset / 8) as usize;
+        let cycle = offset % 8;
+        if self.dot == 320 {
+            self.sprite_count = self.secondary_count;
+            self.sprite_zero = self.secondary_has_zero;
+        }
+        if cycle != 4 && cycle != 6 {
+            return;
+        }
+
+        let oam = &self.secondary_oam[slot * 4..slot * 4 + 4];
+        let (y, tile, attributes, x) = (oam[0], oam[1], oam[2], oam[3]);
+        let control = interface.control();
+        let height = control.sprite_y;
+        let mut row = self.scanline.wrapping_sub(y as u16) % height;
+        if attributes & SPRITE_FLIP_VERTICAL != 0 {
+            row = height - 1 - row;
+        }
+        // 8x16 sprites take their pattern table from bit 0 of the
+        // tile index, the top half being the even tile.
+        let (table, tile) = if height == 16 {
+            ((tile as u16 & 1) * 0x1000, (tile & 0xfe) as u16 + row / 8)
+        } else {
+            (control.sprite_pattern_table, tile as u16)
+        };
+        let addr = table + tile * 16 + row % 8;
+
+        let mut pattern = interface.fetch(cartridge, if cycle == 4 { addr } else { addr + 8 });
+        if attributes & SPRITE_FLIP_HORIZONTAL != 0 {
+            pattern = pattern.reverse_bits();
+        }
+        let sprite = &mut self.sprites[slot];
+        sprite.x = x;
+        sprite.attributes = attributes;
+        if slot >= self.secondary_count {
+            pattern = 0;
+        }
+        if cycle == 4 {
+            sprite.pattern_lo = pattern;
+        } else {
+            sprite.pattern_hi = pattern;
+        }
+    }
+
+    /// The first opaque sprite pixel at the current dot, with the
+    /// palette and colour packed as for the background, along with
+    /// the sprite's attributes and whether it was sprite zero.
+    fn sprite_pixel(&self, interface: &PpuInterface) -> Option<(u8, u8, bool)> {
+        let x = self.dot - 1;
+        let mask = interface.mask();
+        if !mask.sprites || (x < 8 && !mask.left_sprites) {
+            return None;
+        }
+        self.sprites[..self.sprite_count].iter().enumerate()
+            .filter_map(|(i, sprite)| {
+                let column = x.wrapping_sub(sprite.x as u16);
+                if column >= 8 {
+                    return None;
+                }
+                let bit = |pattern: u8| (pattern >> (7 - column)) & 1;
+                let colour = bit(sprite.pattern_hi) << 1 | bit(sprite.pattern_lo);
+                if colour == 0 {
+                    return None;
+                }
+                let palette = sprite.attributes & SPRITE_PALETTE;
+                Some((palette << 2 | colour, sprite.attributes, i == 0 && self.sprite_zero))
+            })
+            .next()
+    }
+
+    fn draw_pixel(&mut self, interface: &mut PpuInterface) {
+        let background = self.background_pixel(interface);
+        let pixel = match self.sprite_pixel(interface) {
+            Some((sprite, attributes, sprite_zero)) => {
+                let opaque_background = background & 0b11 != 0;
+                // Sprite zero hits never happen on the last dot.
+                if sprite_zero && opaque_background && self.dot != WIDTH as u16 {
+                    interface.set_sprite_zero_hit(true);
+                }
+                if opaque_background && attributes & SPRITE_BEHIND_BACKGROUND != 0 {
+                    background
+                } else {
+                    // Sprite palettes follow those for the background.
+                    0x10 | sprite
+                }
+            },
+            None => background,
+        };
+        // Transparent pixels show the backdrop colour.
+        let palette_addr = if pixel & 0b11 == 0 {
+            0x3f00
+        } else {
+            0x3f00 + pixel as u16
+        };
+        let mask = interface.mask();
+        let colour = match mask.colour_mode {
+            ColourMode::Colour => interface.read_palette(palette_addr),
+            // Greyscale drops the hue, leaving the grey of each row.
+            ColourMode::Monochrome => interface.read_palette(palette_addr) & 0x30,
+        };
+        let emphasis = (mask.red as u16) | (mask.green as u16) << 1 | (mask.blue as u16) << 2;
+        let x = (self.dot - 1) as usize;
+        let y = self.scanline as usize;
+        self.framebuffer[y * WIDTH + x] = colour as u16 | emphasis << 6;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::rom::Mirroring;
+
+    /// Run the PPU until the end of the second frame's picture, the
+    /// first frame starting without the tiles prefetched by the
+    /// pre-render scanline.
+    fn run_frames(ppu: &mut Ppu, interface: &mut PpuInterface, cartridge: &mut Cartridge) {
+        while !(ppu.scanline == HEIGHT as u16 && ppu.dot == 0) {
+            ppu.step(interface, cartridge);
+        }
+        for _ in 0..(DOTS as usize) * (SCANLINES as usize) {
+            ppu.step(interface, cartridge);
+        }
+    }
+
+    /// An interface and a cartridge with vertical mirroring with the
+    /// first tile of the pattern table striped with colours 1, 2 and 3.
+    fn striped_interface() -> (PpuInterface, Cartridge) {
+        let mut interface = PpuInterface::new();
+        let mut cartridge = Cartridge::blank(Mirroring::Vertical);
+        for row in 0..8 {
+            interface.write(&mut cartridge, row, 0b01011010);
+            interface.write(&mut cartridge, row + 8, 0b00111100);
+        }
+        for (i, &colour) in [0x0f, 0x11, 0x22, 0x33].iter().enumerate() {
+            interface.write(&mut cartridge, 0x3f00 + i as u16, colour);
+            interface.write(&mut cartridge, 0x3f04 + i as u16, colour + 1);
+        }
+        (interface, cartridge)
+    }
+
+    #[test]
+    fn test_background_disabled_draws_backdrop() {
+        let (mut interface, mut cartridge) = striped_interface();
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+        assert!(ppu.framebuffer().iter().all(|&pixel| pixel == 0x0f));
+    }
+
+    #[test]
+    fn test_background_tiles() {
+        let (mut interface, mut cartridge) = striped_interface();
+        // Background and left column enabled.
+        interface.write_register(&mut cartridge, 0x2001, 0b00001010);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        let row = &ppu.framebuffer()[..8];
+        assert_eq!(row, &[0x0f, 0x11, 0x22, 0x33, 0x33, 0x22, 0x11, 0x0f]);
+        // Every tile of the nametable is the same.
+        assert_eq!(&ppu.framebuffer()[WIDTH * 100 + 248..WIDTH * 100 + 256], row);
+    }
+
+    #[test]
+    fn test_background_attributes_and_left_column() {
+        let (mut interface, mut cartridge) = striped_interface();
+        // The bottom right quadrant of the first attribute
+        // byte uses palette 1, starting at tile (2, 2).
+        interface.write(&mut cartridge, 0x23c0, 0b01000000);
+        // Background enabled but hidden in the left column.
+        interface.write_register(&mut cartridge, 0x2001, 0b00001000);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        assert_eq!(&ppu.framebuffer()[..8], &[0x0f; 8]);
+        assert_eq!(ppu.framebuffer()[8 + 3], 0x33);
+        assert_eq!(ppu.framebuffer()[WIDTH * 16 + 16 + 3], 0x34);
+    }
+
+    #[test]
+    fn test_background_scroll() {
+        let (mut interface, mut cartridge) = striped_interface();
+        // Tile 1 is solid colour 1, put it on the second row of
+        // tiles of the second nametable across.
+        for row in 0..8 {
+            interface.write(&mut cartridge, 0x10 + row, 0xff);
+        }
+        interface.write(&mut cartridge, 0x2400 + 32, 0x01);
+        // Scroll right by 259 pixels and down by 4.
+        interface.write_register(&mut cartridge, 0x2000, 0b01);
+        interface.write_register(&mut cartridge, 0x2005, 3);
+        interface.write_register(&mut cartridge, 0x2005, 4);
+        interface.write_register(&mut cartridge, 0x2001, 0b00001010);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        assert_eq!(&ppu.framebuffer()[..8], &[0x33, 0x33, 0x22, 0x11, 0x0f, 0x0f, 0x11, 0x22]);
+        // The solid tile starts 4 lines down, cut off 3 pixels in.
+        assert_eq!(&ppu.framebuffer()[3 * WIDTH..3 * WIDTH + 6], &[0x33, 0x33, 0x22, 0x11, 0x0f, 0x0f]);
+        assert_eq!(&ppu.framebuffer()[4 * WIDTH..4 * WIDTH + 6], &[0x11, 0x11, 0x11, 0x11, 0x11, 0x0f]);
+    }
+
+    /// Add a sprite of tile 1, which is solid colour 1
+    /// on its left half and colour 2 on its right.
+    fn add_sprite(interface: &mut PpuInterface, cartridge: &mut Cartridge, index: u8, x: u8, y: u8, attributes: u8) {
+        for row in 0..8 {
+            interface.write(cartridge, 0x10 + row, 0xf0);
+            interface.write(cartridge, 0x18 + row, 0x0f);
+        }
+        interface.write(cartridge, 0x3f11, 0x15);
+        interface.write(cartridge, 0x3f12, 0x16);
+        let addr = index * 4;
+        interface.write_spr(addr, y);
+        interface.write_spr(addr + 1, 0x01);
+        interface.write_spr(addr + 2, attributes);
+        interface.write_spr(addr + 3, x);
+    }
+
+    #[test]
+    fn test_sprites() {
+        let (mut interface, mut cartridge) = striped_interface();
+        // Move every sprite off screen.
+        for i in 0..64 {
+            interface.write_spr(i * 4, 0xff);
+        }
+        add_sprite(&mut interface, &mut cartridge, 0, 16, 9, 0);
+        add_sprite(&mut interface, &mut cartridge, 1, 40, 9, SPRITE_FLIP_HORIZONTAL);
+        // Sprites only, including the left column.
+        interface.write_register(&mut cartridge, 0x2001, 0b00010100);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        let line = |y: usize, x: usize| &ppu.framebuffer()[y * WIDTH + x..y * WIDTH + x + 8];
+        // Sprites are drawn from the line after their Y coordinate.
+        assert_eq!(line(9, 16), &[0x0f; 8]);
+        assert_eq!(line(10, 16), &[0x15, 0x15, 0x15, 0x15, 0x16, 0x16, 0x16, 0x16]);
+        assert_eq!(line(17, 16), &[0x15, 0x15, 0x15, 0x15, 0x16, 0x16, 0x16, 0x16]);
+        assert_eq!(line(18, 16), &[0x0f; 8]);
+        assert_eq!(line(10, 40), &[0x16, 0x16, 0x16, 0x16, 0x15, 0x15, 0x15, 0x15]);
+        // Neither is sprite zero hit without a background.
+        assert_eq!(interface.read_register(&mut cartridge, 0x2002) & 0x60, 0);
+    }
+
+    #[test]
+    fn test_sprite_priority_and_zero_hit() {
+        let (mut interface, mut cartridge) = striped_interface();
+        for i in 0..64 {
+            interface.write_spr(i * 4, 0xff);
+        }
+        add_sprite(&mut interface, &mut cartridge, 0, 16, 9, SPRITE_BEHIND_BACKGROUND);
+        // Sprites and background, including the left column.
+        interface.write_register(&mut cartridge, 0x2001, 0b00011110);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        // The sprite only shows through the transparent background pixels.
+        let start = 10 * WIDTH + 16;
+        assert_eq!(&ppu.framebuffer()[start..start + 8],
+                   &[0x15, 0x11, 0x22, 0x33, 0x33, 0x22, 0x11, 0x16]);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2002) & 0x60, 0x40);
+    }
+
+    #[test]
+    fn test_sprite_overflow() {
+        let (mut interface, mut cartridge) = striped_interface();
+        for i in 0..64 {
+            interface.write_spr(i * 4, 0xff);
+        }
+        for i in 0..8 {
+            add_sprite(&mut interface, &mut cartridge, i, i * 8, 100, 0);
+        }
+        interface.write_register(&mut cartridge, 0x2001, 0b00010100);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2002) & 0x20, 0);
+
+        add_sprite(&mut interface, &mut cartridge, 8, 64, 100, 0);
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+        assert_eq!(interface.read_register(&mut cartridge, 0x2002) & 0x20, 0x20);
+        // Only the first 8 sprites are drawn.
+        assert_eq!(ppu.framebuffer()[101 * WIDTH + 56], 0x15);
+        assert_eq!(ppu.framebuffer()[101 * WIDTH + 64], 0x0f);
+    }
+
+    #[test]
+    fn test_tall_sprites() {
+        let (mut interface, mut cartridge) = striped_interface();
+        for i in 0..64 {
+            interface.write_spr(i * 4, 0xff);
+        }
+        // Tile 0 in 8x16 mode draws the striped tile 0 above tile 1
+        // from the first pattern table, here flipped vertically.
+        add_sprite(&mut interface, &mut cartridge, 0, 16, 9, SPRITE_FLIP_VERTICAL);
+        interface.write_spr(1, 0x00);
+        interface.write_register(&mut cartridge, 0x2000, 0b00100000);
+        interface.write_register(&mut cartridge, 0x2001, 0b00010100);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        assert_eq!(ppu.framebuffer()[10 * WIDTH + 16], 0x15);
+        assert_eq!(ppu.framebuffer()[25 * WIDTH + 16], 0x0f);
+        assert_eq!(ppu.framebuffer()[25 * WIDTH + 16 + 1], 0x15);
+        assert_eq!(ppu.framebuffer()[26 * WIDTH + 16], 0x0f);
+    }
+
+    fn step_to(ppu: &mut Ppu, interface: &mut PpuInterface, cartridge: &mut Cartridge, scanline: u16, dot: u16) {
+        while !(ppu.scanline() == scanline && ppu.dot() == dot) {
+            ppu.step(interface, cartridge);
+        }
+    }
+
+    #[test]
+    fn test_vblank_timing() {
+        let (mut interface, mut cartridge) = striped_interface();
+        interface.write_register(&mut cartridge, 0x2000, 0b10000000);
+        let mut ppu = Ppu::new();
+
+        step_to(&mut ppu, &mut interface, &mut cartridge, VBLANK_SCANLINE, 1);
+        assert!(!interface.nmi());
+        step_to(&mut ppu, &mut interface, &mut cartridge, VBLANK_SCANLINE, 2);
+        assert!(interface.nmi());
+
+        step_to(&mut ppu, &mut interface, &mut cartridge, PRE_RENDER_SCANLINE, 2);
+        assert!(!interface.nmi());
+        assert_eq!(interface.read_register(&mut cartridge, 0x2002) & 0x80, 0);
+    }
+
+    /// The number of dots in each of the next 4 frames.
+    fn frame_lengths(ppu: &mut Ppu, interface: &mut PpuInterface, cartridge: &mut Cartridge) -> Vec<usize> {
+        (0..4).map(|_| {
+            let frame = ppu.frame_count();
+            let mut dots = 0;
+            while ppu.frame_count() == frame {
+                ppu.step(interface, cartridge);
+                dots += 1;
+            }
+            dots
+        }).collect()
+    }
+
+    #[test]
+    fn test_odd_frames_skip_a_dot() {
+        let (mut interface, mut cartridge) = striped_interface();
+        let mut ppu = Ppu::new();
+        assert_eq!(frame_lengths(&mut ppu, &mut interface, &mut cartridge), vec![89342; 4]);
+
+        interface.write_register(&mut cartridge, 0x2001, 0b00001000);
+        assert_eq!(frame_lengths(&mut ppu, &mut interface, &mut cartridge), vec![89342, 89341, 89342, 89341]);
+    }
+
+    #[test]
+    fn test_greyscale_and_emphasis() {
+        let (mut interface, mut cartridge) = striped_interface();
+        // Greyscale with red and blue emphasis.
+        interface.write_register(&mut cartridge, 0x2001, 0b10101011);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+        assert_eq!(&ppu.framebuffer()[..4], &[0x140, 0x150, 0x160, 0x170]);
+    }
+
+    #[test]
+    fn test_frame_is_swapped_in_at_vblank() {
+        let (mut interface, mut cartridge) = striped_interface();
+        interface.write_register(&mut cartridge, 0x2001, 0b00001010);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
+
+        interface.write(&mut cartridge, 0x3f01, 0x21);
+        step_to(&mut ppu, &mut interface, &mut cartridge, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.frame()[1], 0x11);
+        step_to(&mut ppu, &mut interface, &mut cartridge, HEIGHT as u16, 0);
+        assert_eq!(ppu.framebuffer()[1], 0x21);
+        assert_eq!(ppu.frame()[1], 0x11);
+        step_to(&mut ppu, &mut interface, &mut cartridge, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.frame()[1], 0x21);
     }
 
-    pub fn step(&mut self, mem: &mut Interconnect) {
+    #[test]
+    fn test_beam_pixels_follow_the_swap() {
+        let (mut interface, mut cartridge) = striped_interface();
+        interface.write_register(&mut cartridge, 0x2001, 0b00001010);
+        let mut ppu = Ppu::new();
+        run_frames(&mut ppu, &mut interface, &mut cartridge);
 
+        step_to(&mut ppu, &mut interface, &mut cartridge, 0, 0);
+        interface.write(&mut cartridge, 0x3f01, 0x21);
+        step_to(&mut ppu, &mut interface, &mut cartridge, VBLANK_SCANLINE, 1);
+        assert_eq!(ppu.beam_pixels()[1], 0x21);
+        // Still the frame just drawn after it's been swapped out.
+        step_to(&mut ppu, &mut interface, &mut cartridge, VBLANK_SCANLINE, 2);
+        assert_eq!(ppu.beam_pixels()[1], 0x21);
+        step_to(&mut ppu, &mut interface, &mut cartridge, PRE_RENDER_SCANLINE, 0);
+        assert_eq!(ppu.beam_pixels()[1], 0x21);
+        step_to(&mut ppu, &mut interface, &mut cartridge, 0, 0);
+        assert_eq!(ppu.beam_pixels()[1], 0x11);
     }
 }
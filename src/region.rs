@@ -1,4 +1,5 @@
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
 pub enum Region {
     NTSC,
     PAL
-}
\ No newline at end of file
+}
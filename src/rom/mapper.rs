@@ -1,47 +1,580 @@
+/// How the PPU's 2K of nametable RAM is arranged in
+/// its 4 nametables: http://wiki.nesdev.com/w/index.php/Mirroring
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Mirroring {
+    /// The top and bottom nametables are the same, for vertical scrolling.
+    Horizontal,
+    /// The left and right nametables are the same, for horizontal scrolling.
+    Vertical,
+    /// All 4 nametables are the same, using the first half of the RAM.
+    SingleScreenLower,
+    /// All 4 nametables are the same, using the second half of the RAM.
+    SingleScreenUpper,
+    /// The cartridge provides another 2K of RAM for 4 distinct nametables.
+    FourScreen,
+}
+
+impl Mirroring {
+    /// Map an address in 0x2000-0x2fff to an offset into nametable RAM.
+    pub fn nametable_offset(self, addr: u16) -> usize {
+        let addr = (addr as usize) % 0x1000;
+        let table = addr / 0x400;
+        let table = match self {
+            Mirroring::Horizontal => table / 2,
+            Mirroring::Vertical => table % 2,
+            Mirroring::SingleScreenLower => 0,
+            Mirroring::SingleScreenUpper => 1,
+            Mirroring::FourScreen => table,
+        };
+        table * 0x400 + addr % 0x400
+    }
+}
+
+/// The hardware on a cartridge which decides where the CPU and PPU's
+/// addresses land in its memory, and may switch nametable mirroring or
+/// raise IRQs: http://wiki.nesdev.com/w/index.php/Mapper
+///
+/// `Cartridge` owns the memory itself, asking the mapper for offsets.
 pub trait Mapper {
-    fn map_sram(&self, addr: u16) -> usize;
+    /// Offset into PRG ROM of a CPU address in 0x8000-0xffff.
     fn map_prg(&self, addr: u16) -> usize;
+
+    /// Offset into PRG RAM of a CPU address in 0x6000-0x7fff,
+    /// or `None` if there is none or it is disabled.
+    fn map_sram(&self, addr: u16) -> Option<usize>;
+
+    /// Offset into CHR ROM or RAM of a PPU address in 0x0000-0x1fff.
     fn map_chr(&self, addr: u16) -> usize;
+
+    /// A CPU write to 0x4020-0xffff, which is where mappers
+    /// decode their registers.
+    fn cpu_write(&mut self, _addr: u16, _x: u8) {}
+
+    /// Every address the PPU puts on its bus, for mappers
+    /// which count rises of A12 to time their IRQs.
+    fn ppu_address(&mut self, _addr: u16) {}
+
+    fn mirroring(&self) -> Mirroring;
+
+    /// Whether the PRG ROM drives the data bus at the same time as
+    /// the CPU when written to, so the mapper sees the two ANDed.
+    fn bus_conflicts(&self) -> bool { false }
+
+    /// Level of the mapper's IRQ output.
+    fn irq(&self) -> bool { false }
+
+    /// Called once for every CPU cycle.
+    fn tick(&mut self) {}
 }
 
 /// NROM Cartridge Mapper
 /// https://wiki.nesdev.com/w/index.php/NROM
 pub struct Mapper0 {
     pub nprg: usize,
+    pub mirroring: Mirroring,
 }
 
 impl Mapper for Mapper0 {
-    fn map_sram(&self, addr: u16) -> usize {
-        (addr - 0x6000) as usize
-    }
-
     fn map_prg(&self, addr: u16) -> usize {
         // NROM mapper can have one or two PRG ROM banks
         // given by self.nprg. If there is only one then
         // the first bank is mirrored.
-        let offset = (addr - 0x8000) % (0x4000 * self.nprg as u16);
-        offset as usize
+        (addr as usize - 0x8000) % (0x4000 * self.nprg.max(1))
+    }
+
+    fn map_sram(&self, addr: u16) -> Option<usize> {
+        Some((addr - 0x6000) as usize)
     }
 
     fn map_chr(&self, addr: u16) -> usize {
-        unimplemented!()
+        // A single fixed 8K bank.
+        addr as usize
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
     }
 }
 
 /// MMC1 Mapper
 /// https://wiki.nesdev.com/w/index.php/MMC1
-pub struct Mapper1;
+///
+/// Its registers are loaded a bit at a time through a serial port
+/// at 0x8000-0xffff, the fifth write picking the register by address.
+pub struct Mapper1 {
+    /// Size of PRG ROM in 16K banks.
+    nprg: usize,
+    /// Size of PRG RAM in 8K banks.
+    nram: usize,
+    // Bits written so far, shifted in from the top. The 1 it
+    // starts with reaching bit 0 means the next write is the fifth.
+    shift: u8,
+    // Mirroring in bits 0-1, PRG bank mode in bits 2-3
+    // and whether CHR is switched in 4K banks in bit 4.
+    control: u8,
+    chr_bank0: u8,
+    chr_bank1: u8,
+    // PRG bank in bits 0-3, bit 4 disables PRG RAM.
+    prg_bank: u8,
+    // The CPU cycle of the last write to the serial port.
+    cycles: u64,
+    last_write: Option<u64>,
+}
+
+const MMC1_SHIFT_RESET: u8 = 0b10000;
+
+impl Mapper1 {
+    pub fn new(nprg: usize, nram: usize) -> Mapper1 {
+        Mapper1 {
+            nprg,
+            nram,
+            shift: MMC1_SHIFT_RESET,
+            // Starts with the last bank fixed at 0xc000,
+            // so the reset vector can be found.
+            control: 0b01100,
+            chr_bank0: 0,
+            chr_bank1: 0,
+            prg_bank: 0,
+            cycles: 0,
+            last_write: None,
+        }
+    }
+
+    fn write_register(&mut self, addr: u16, x: u8) {
+        match addr {
+            0x8000 ..= 0x9fff => self.control = x,
+            0xa000 ..= 0xbfff => self.chr_bank0 = x,
+            0xc000 ..= 0xdfff => self.chr_bank1 = x,
+            _ => self.prg_bank = x,
+        }
+    }
+
+    /// SUROM's 512K of PRG ROM is split in two 256K halves,
+    /// picked by bit 4 of the CHR bank as its CHR is all RAM.
+    fn prg_outer_bank(&self) -> usize {
+        if self.nprg > 16 {
+            (self.chr_bank0 & 0x10) as usize
+        } else {
+            0
+        }
+    }
+}
 
 impl Mapper for Mapper1 {
-    fn map_sram(&self, addr: u16) -> usize {
-        0
+    fn map_prg(&self, addr: u16) -> usize {
+        let bank = (self.prg_bank & 0x0f) as usize;
+        let last = self.nprg.clamp(1, 16) - 1;
+        let bank = match (self.control >> 2) & 0b11 {
+            // Switch 32K at 0x8000, ignoring the low bit.
+            0 | 1 => (bank & !1) | ((addr as usize - 0x8000) / 0x4000),
+            // Fix the first bank at 0x8000 and switch 16K at 0xc000.
+            2 => if addr < 0xc000 { 0 } else { bank },
+            // Fix the last bank at 0xc000 and switch 16K at 0x8000.
+            _ => if addr < 0xc000 { bank } else { last },
+        };
+        (self.prg_outer_bank() | bank) * 0x4000 + (addr as usize % 0x4000)
+    }
+
+    fn map_sram(&self, addr: u16) -> Option<usize> {
+        if self.prg_bank & 0x10 != 0 {
+            return None;
+        }
+        // SOROM has 16K and SXROM 32K of PRG RAM,
+        // banked with the bits above the CHR bank.
+        let bank = match self.nram {
+            0 | 1 => 0,
+            2 => (self.chr_bank0 >> 3) & 0b1,
+            _ => (self.chr_bank0 >> 2) & 0b11,
+        };
+        Some(bank as usize * 0x2000 + (addr - 0x6000) as usize)
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        let bank = if self.control & 0x10 == 0 {
+            // A single 8K bank, ignoring the low bit.
+            (self.chr_bank0 & 0x1e) as usize + (addr as usize / 0x1000)
+        } else if addr < 0x1000 {
+            self.chr_bank0 as usize
+        } else {
+            self.chr_bank1 as usize
+        };
+        bank * 0x1000 + (addr as usize % 0x1000)
+    }
+
+    fn cpu_write(&mut self, addr: u16, x: u8) {
+        if addr < 0x8000 {
+            return;
+        }
+        // The serial port misses the second of two writes on
+        // consecutive cycles, as read-modify-write instructions do.
+        let consecutive = self.last_write == Some(self.cycles.wrapping_sub(1));
+        self.last_write = Some(self.cycles);
+        if consecutive {
+            return;
+        }
+
+        if x & 0x80 != 0 {
+            self.shift = MMC1_SHIFT_RESET;
+            self.control |= 0b01100;
+            return;
+        }
+        let done = self.shift & 1 != 0;
+        self.shift = (self.shift >> 1) | ((x & 1) << 4);
+        if done {
+            let value = self.shift;
+            self.write_register(addr, value);
+            self.shift = MMC1_SHIFT_RESET;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        match self.control & 0b11 {
+            0 => Mirroring::SingleScreenLower,
+            1 => Mirroring::SingleScreenUpper,
+            2 => Mirroring::Vertical,
+            _ => Mirroring::Horizontal,
+        }
+    }
+
+    fn tick(&mut self) {
+        self.cycles += 1;
+    }
+}
+
+/// UxROM Mapper
+/// https://wiki.nesdev.com/w/index.php/UxROM
+pub struct Mapper2 {
+    /// Size of PRG ROM in 16K banks.
+    nprg: usize,
+    mirroring: Mirroring,
+    bank: u8,
+}
+
+impl Mapper2 {
+    pub fn new(nprg: usize, mirroring: Mirroring) -> Mapper2 {
+        Mapper2 { nprg, mirroring, bank: 0 }
+    }
+}
+
+impl Mapper for Mapper2 {
+    fn map_prg(&self, addr: u16) -> usize {
+        // 16K switched at 0x8000, with the last bank fixed at 0xc000.
+        let bank = if addr < 0xc000 {
+            self.bank as usize
+        } else {
+            self.nprg.max(1) - 1
+        };
+        bank * 0x4000 + (addr as usize % 0x4000)
+    }
+
+    fn map_sram(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        addr as usize
+    }
+
+    fn cpu_write(&mut self, addr: u16, x: u8) {
+        if addr >= 0x8000 {
+            self.bank = x & 0x0f;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+/// CNROM Mapper
+/// https://wiki.nesdev.com/w/index.php/CNROM
+pub struct Mapper3 {
+    /// Size of PRG ROM in 16K banks.
+    nprg: usize,
+    mirroring: Mirroring,
+    chr_bank: u8,
+}
+
+impl Mapper3 {
+    pub fn new(nprg: usize, mirroring: Mirroring) -> Mapper3 {
+        Mapper3 { nprg, mirroring, chr_bank: 0 }
+    }
+}
+
+impl Mapper for Mapper3 {
+    fn map_prg(&self, addr: u16) -> usize {
+        // Fixed like NROM, mirrored if there is only 16K.
+        (addr as usize - 0x8000) % (0x4000 * self.nprg.max(1))
+    }
+
+    fn map_sram(&self, _addr: u16) -> Option<usize> {
+        None
+    }
+
+    fn map_chr(&self, addr: u16) -> usize {
+        self.chr_bank as usize * 0x2000 + addr as usize
+    }
+
+    fn cpu_write(&mut self, addr: u16, x: u8) {
+        if addr >= 0x8000 {
+            self.chr_bank = x;
+        }
+    }
+
+    fn bus_conflicts(&self) -> bool { true }
+
+    fn mirroring(&self) -> Mirroring {
+        self.mirroring
+    }
+}
+
+/// AxROM Mapper
+/// https://wiki.nesdev.com/w/index.php/AxROM
+pub struct Mapper7 {
+    // 32K PRG bank in bits 0-2, bit 4 picks the nametable.
+    bank: u8,
+}
+
+impl Mapper7 {
+    pub fn new() -> Mapper7 {
+        Mapper7 { bank: 0 }
     }
+}
 
+impl Mapper for Mapper7 {
     fn map_prg(&self, addr: u16) -> usize {
-        0
+        (self.bank & 0b111) as usize * 0x8000 + (addr as usize - 0x8000)
+    }
+
+    fn map_sram(&self, _addr: u16) -> Option<usize> {
+        None
     }
 
     fn map_chr(&self, addr: u16) -> usize {
-        0
+        addr as usize
+    }
+
+    fn cpu_write(&mut self, addr: u16, x: u8) {
+        if addr >= 0x8000 {
+            self.bank = x;
+        }
+    }
+
+    fn mirroring(&self) -> Mirroring {
+        if self.bank & 0x10 == 0 {
+            Mirroring::SingleScreenLower
+        } else {
+            Mirroring::SingleScreenUpper
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{Cartridge, Error};
+
+    #[test]
+    fn test_nametable_offset() {
+        let offsets = |mirroring: Mirroring| {
+            [0x2000, 0x2400, 0x2800, 0x2c00, 0x2c05].iter()
+                .map(|&addr| mirroring.nametable_offset(addr))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(offsets(Mirroring::Horizontal), vec![0x000, 0x000, 0x400, 0x400, 0x405]);
+        assert_eq!(offsets(Mirroring::Vertical), vec![0x000, 0x400, 0x000, 0x400, 0x405]);
+        assert_eq!(offsets(Mirroring::SingleScreenLower), vec![0x000, 0x000, 0x000, 0x000, 0x005]);
+        assert_eq!(offsets(Mirroring::SingleScreenUpper), vec![0x400, 0x400, 0x400, 0x400, 0x405]);
+        assert_eq!(offsets(Mirroring::FourScreen), vec![0x000, 0x400, 0x800, 0xc00, 0xc05]);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_mapper0_mirrors_16k_prg() {
+        let mapper = Mapper0 { nprg: 1, mirroring: Mirroring::Vertical };
+        assert_eq!(mapper.map_prg(0x8000), 0x0000);
+        assert_eq!(mapper.map_prg(0xc123), 0x0123);
+        let mapper = Mapper0 { nprg: 2, mirroring: Mirroring::Vertical };
+        assert_eq!(mapper.map_prg(0xc123), 0x4123);
+        // A header with no PRG ROM doesn't bring the mapper down.
+        let mapper = Mapper0 { nprg: 0, mirroring: Mirroring::Vertical };
+        assert_eq!(mapper.map_prg(0xc123), 0x0123);
+    }
+
+    #[test]
+    fn test_writes_leave_prg_rom_alone() {
+        let mut cartridge = Cartridge::blank(Mirroring::Horizontal);
+        cartridge.cpu_write(0x8000, 0x12);
+        assert_eq!(cartridge.cpu_read(0x8000), Some(0x00));
+        cartridge.cpu_write(0x6000, 0x34);
+        assert_eq!(cartridge.cpu_read(0x6000), Some(0x34));
+        assert_eq!(cartridge.cpu_read(0x5000), None);
+        assert_eq!(cartridge.mirroring(), Mirroring::Horizontal);
+    }
+
+    /// Load an MMC1 register through the serial port, leaving
+    /// a gap between writes so none are ignored.
+    fn load(mapper: &mut Mapper1, addr: u16, value: u8) {
+        for i in 0..5 {
+            mapper.tick();
+            mapper.tick();
+            mapper.cpu_write(addr, value >> i);
+        }
+    }
+
+    #[test]
+    fn test_mmc1_serial_port() {
+        let mut mapper = Mapper1::new(8, 1);
+        load(&mut mapper, 0x8000, 0b00010);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        load(&mut mapper, 0x9fff, 0b00011);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+
+        // Bit 7 throws away the bits written so far.
+        mapper.tick();
+        mapper.tick();
+        mapper.cpu_write(0x8000, 0x00);
+        mapper.tick();
+        mapper.tick();
+        mapper.cpu_write(0x8000, 0x80);
+        load(&mut mapper, 0x8000, 0b00001);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_mmc1_ignores_consecutive_writes() {
+        let mut mapper = Mapper1::new(8, 1);
+        load(&mut mapper, 0x8000, 0b00010);
+        // Like INC on a register, the second write is missed,
+        // so it takes one more bit to load the register.
+        for &bit in &[1, 1, 1, 1] {
+            mapper.tick();
+            mapper.tick();
+            mapper.cpu_write(0x8000, bit);
+        }
+        mapper.tick();
+        mapper.cpu_write(0x8000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Vertical);
+        mapper.tick();
+        mapper.tick();
+        mapper.cpu_write(0x8000, 0);
+        assert_eq!(mapper.mirroring(), Mirroring::Horizontal);
+    }
+
+    #[test]
+    fn test_mmc1_prg_banks() {
+        let mut mapper = Mapper1::new(8, 1);
+        load(&mut mapper, 0xe000, 2);
+        // The last bank starts fixed at 0xc000.
+        assert_eq!(mapper.map_prg(0x8123), 2 * 0x4000 + 0x123);
+        assert_eq!(mapper.map_prg(0xc123), 7 * 0x4000 + 0x123);
+
+        load(&mut mapper, 0x8000, 0b01000);
+        assert_eq!(mapper.map_prg(0x8123), 0x123);
+        assert_eq!(mapper.map_prg(0xc123), 2 * 0x4000 + 0x123);
+
+        load(&mut mapper, 0xe000, 3);
+        load(&mut mapper, 0x8000, 0b00000);
+        assert_eq!(mapper.map_prg(0x8123), 2 * 0x4000 + 0x123);
+        assert_eq!(mapper.map_prg(0xc123), 3 * 0x4000 + 0x123);
+    }
+
+    #[test]
+    fn test_mmc1_chr_banks() {
+        let mut mapper = Mapper1::new(8, 1);
+        load(&mut mapper, 0xa000, 3);
+        load(&mut mapper, 0xc000, 5);
+        // The low bit is ignored in 8K mode.
+        load(&mut mapper, 0x8000, 0b00000);
+        assert_eq!(mapper.map_chr(0x0123), 2 * 0x1000 + 0x123);
+        assert_eq!(mapper.map_chr(0x1123), 3 * 0x1000 + 0x123);
+        load(&mut mapper, 0x8000, 0b10000);
+        assert_eq!(mapper.map_chr(0x0123), 3 * 0x1000 + 0x123);
+        assert_eq!(mapper.map_chr(0x1123), 5 * 0x1000 + 0x123);
+    }
+
+    #[test]
+    fn test_mmc1_prg_ram() {
+        let mut mapper = Mapper1::new(8, 1);
+        assert_eq!(mapper.map_sram(0x6123), Some(0x123));
+        load(&mut mapper, 0xe000, 0x10);
+        assert_eq!(mapper.map_sram(0x6123), None);
+    }
+
+    #[test]
+    fn test_surom_and_sxrom() {
+        let mut mapper = Mapper1::new(32, 4);
+        load(&mut mapper, 0xe000, 1);
+        assert_eq!(mapper.map_prg(0x8000), 0x4000);
+        assert_eq!(mapper.map_prg(0xc000), 15 * 0x4000);
+        // Bit 4 of the CHR bank picks the second 256K
+        // and bits 2-3 the 8K bank of PRG RAM.
+        load(&mut mapper, 0xa000, 0b11000);
+        assert_eq!(mapper.map_prg(0x8000), 17 * 0x4000);
+        assert_eq!(mapper.map_prg(0xc000), 31 * 0x4000);
+        assert_eq!(mapper.map_sram(0x6123), Some(2 * 0x2000 + 0x123));
+    }
+
+    #[test]
+    fn test_uxrom_banks() {
+        let mut mapper = Mapper2::new(8, Mirroring::Vertical);
+        assert_eq!(mapper.map_prg(0x8123), 0x123);
+        assert_eq!(mapper.map_prg(0xc123), 7 * 0x4000 + 0x123);
+        mapper.cpu_write(0x8000, 5);
+        assert_eq!(mapper.map_prg(0x8123), 5 * 0x4000 + 0x123);
+        assert_eq!(mapper.map_prg(0xc123), 7 * 0x4000 + 0x123);
+        assert_eq!(mapper.map_sram(0x6000), None);
+    }
+
+    #[test]
+    fn test_cnrom_bus_conflicts() {
+        // 16K of PRG ROM holding 0xff apart from 0x01 at 0x8000,
+        // and 4 banks of CHR ROM each filled with its number.
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 1, 4, 0x30, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        let mut prg = vec![0xff; 0x4000];
+        prg[0] = 0x01;
+        data.extend(prg);
+        for bank in 0..4 {
+            data.extend(vec![bank; 0x2000]);
+        }
+        let mut cartridge = Cartridge::new(data).unwrap();
+        assert_eq!(cartridge.ppu_read(0x1234), 0);
+        cartridge.cpu_write(0x8001, 2);
+        assert_eq!(cartridge.ppu_read(0x1234), 2);
+        // Writing 3 over the 0x01 in ROM selects bank 1.
+        cartridge.cpu_write(0x8000, 3);
+        assert_eq!(cartridge.ppu_read(0x1234), 1);
+    }
+
+    #[test]
+    fn test_axrom_banks() {
+        let mut mapper = Mapper7::new();
+        assert_eq!(mapper.map_prg(0xc123), 0x4123);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenLower);
+        mapper.cpu_write(0x8000, 0x13);
+        assert_eq!(mapper.map_prg(0xc123), 3 * 0x8000 + 0x4123);
+        assert_eq!(mapper.mirroring(), Mirroring::SingleScreenUpper);
+    }
+
+    #[test]
+    fn test_no_prg_rom() {
+        let data = vec![b'N', b'E', b'S', 0x1a, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        match Cartridge::new(data) {
+            Err(Error::Parse(_)) => (),
+            _ => panic!("expected a ROM without PRG ROM to be rejected"),
+        }
+    }
+
+    #[test]
+    fn test_unsupported_mapper() {
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 1, 1, 0x40, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 16384 + 8192, 0);
+        match Cartridge::new(data) {
+            Err(err @ Error::UnsupportedMapper(4)) => {
+                #[allow(deprecated)]
+                let cause = ::std::error::Error::cause(&err);
+                assert!(cause.is_none());
+            },
+            _ => panic!("expected mapper 4 to be unsupported"),
+        }
+    }
+}
@@ -1,4 +1,5 @@
 mod mapper;
 mod rom;
 
-pub use self::rom::{Cartridge, Result};
\ No newline at end of file
+pub use self::mapper::{Mirroring};
+pub use self::rom::{Cartridge, Error, Result};
\ No newline at end of file
@@ -5,20 +5,18 @@
 
 // TODO: support Unif ROM format.
 
-use std::borrow::{Cow};
 use std::error;
 use std::fmt;
 use std::fs::{File};
 use std::io;
 use std::io::{Read};
-use std::mem;
 use std::path::{Path};
 use std::result;
 use std::str;
 
 use nom;
 
-use super::mapper::{Mapper, Mapper0, Mapper1};
+use super::mapper::{Mapper, Mapper0, Mapper1, Mapper2, Mapper3, Mapper7, Mirroring};
 
 struct INesHeader {
     pub nprg: u8,
@@ -29,11 +27,27 @@ struct INesHeader {
 }
 
 impl INesHeader {
-    pub fn mapper(&self) -> Box<Mapper> {
-        match ((self.control1 & 0xf0) >> 4) | (self.control2 & 0xf0) {
-            0x00 => Box::new(Mapper0 { nprg: self.nprg as usize }) as Box<Mapper>,
-            0x01 => Box::new(Mapper1) as Box<Mapper>,
-            n => panic!("Unrecognised mapper: {:#x}", n),
+    pub fn mapper(&self) -> Result<Box<dyn Mapper>> {
+        Ok(match ((self.control1 & 0xf0) >> 4) | (self.control2 & 0xf0) {
+            0x00 => Box::new(Mapper0 {
+                nprg: self.nprg as usize,
+                mirroring: self.mirroring(),
+            }),
+            0x01 => Box::new(Mapper1::new(self.nprg as usize, self.nram.max(1) as usize)),
+            0x02 => Box::new(Mapper2::new(self.nprg as usize, self.mirroring())),
+            0x03 => Box::new(Mapper3::new(self.nprg as usize, self.mirroring())),
+            0x07 => Box::new(Mapper7::new()),
+            n => return Err(Error::UnsupportedMapper(n)),
+        })
+    }
+
+    pub fn mirroring(&self) -> Mirroring {
+        if (self.control1 & 0b1000) != 0 {
+            Mirroring::FourScreen
+        } else if (self.control1 & 0b1) != 0 {
+            Mirroring::Vertical
+        } else {
+            Mirroring::Horizontal
         }
     }
 
@@ -54,7 +68,9 @@ pub struct Cartridge {
     sram: Vec<u8>, // Save RAM (i.e. PRG RAM)
     prg: Vec<u8>,
     chr: Vec<u8>,
-    mapper: Box<Mapper>,
+    // Cartridges without CHR ROM have 8K of CHR RAM instead.
+    chr_ram: bool,
+    mapper: Box<dyn Mapper>,
 }
 
 impl fmt::Debug for Cartridge {
@@ -77,16 +93,18 @@ named!(parse_header<INesHeader>,
         nram: call!( nom::le_u8 ) ~
         count!( call!( nom::le_u8 ), 7 ) ,
         || INesHeader {
-            nprg: nprg,
-            nchr: nchr,
-            control1: control1,
-            control2: control2,
-            nram: nram,
+            nprg,
+            nchr,
+            control1,
+            control2,
+            nram,
         }
     )
 );
 
-named!(parse_cartridge<Cartridge>,
+// Parse the header and the ROM after it, leaving the
+// mapper to be checked once it's known to be well formed.
+named!(parse_cartridge<(INesHeader, Vec<u8>, Vec<u8>)>,
     complete!(
         chain!(
             header: parse_header ~
@@ -105,12 +123,7 @@ named!(parse_cartridge<Cartridge>,
                 call!( nom::le_u8 ),
                 8192 * (header.nchr as usize)
             ) ,
-            || Cartridge {
-                sram: header.sram(),
-                prg: prg,
-                chr: chr,
-                mapper: header.mapper(),
-            }
+            || (header, prg, chr)
         )
     )
 );
@@ -120,63 +133,136 @@ pub type Result<T> = result::Result<T, Error>;
 impl Cartridge {
     pub fn new(data: Vec<u8>) -> Result<Cartridge> {
         // try!(parse_cartridge(&data))
-        match parse_cartridge(&data) {
-            nom::IResult::Done(_, cartridge) => Ok(cartridge),
-            nom::IResult::Error(_) => result::Result::Err(
-                Error::Parse("Could not parse ROM, unrecognised format.".into())
+        let (header, prg, chr) = match parse_cartridge(&data) {
+            nom::IResult::Done(_, parts) => parts,
+            nom::IResult::Error(_) => return result::Result::Err(
+                Error::Parse("Could not parse ROM, unrecognised format.")
             ),
             _ => unreachable!(),
+        };
+        if header.nprg == 0 {
+            return result::Result::Err(Error::Parse("ROM has no PRG ROM."));
         }
+        Ok(Cartridge {
+            sram: header.sram(),
+            prg,
+            chr: if header.nchr == 0 { vec![0; 8192] } else { chr },
+            chr_ram: header.nchr == 0,
+            mapper: header.mapper()?,
+        })
     }
 
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Cartridge> {
-        let mut file = try!(File::open(path));
+        let mut file = File::open(path)?;
         let mut buf = Vec::new();
         file.read_to_end(&mut buf).unwrap();
         Cartridge::new(buf)
     }
 
-    pub fn read(&self, addr: u16) -> u8 {
+    /// The nametable mirroring, either wired up on
+    /// the cartridge or chosen by the mapper.
+    pub fn mirroring(&self) -> Mirroring {
+        self.mapper.mirroring()
+    }
+
+    /// Read from the CPU's 0x4020-0xffff, `None` being
+    /// where nothing on the cartridge responds.
+    pub fn cpu_read(&self, addr: u16) -> Option<u8> {
         match addr {
-            0x0000 ... 0x1fff => {
-                let offset = self.mapper.map_chr(addr);
-                self.chr[offset]
+            0x4020 ..= 0x5fff => None,
+            0x6000 ..= 0x7fff => {
+                self.mapper.map_sram(addr)
+                    .map(|offset| self.sram[offset % self.sram.len()])
             },
-            0x6000 ... 0x7fff => {
-                let offset = self.mapper.map_sram(addr);
-                self.sram[offset]
-            },
-            0x8000 ... 0xffff => {
+            0x8000 ..= 0xffff => {
                 let offset = self.mapper.map_prg(addr);
-                self.prg[offset]
+                Some(self.prg[offset % self.prg.len()])
             },
             _ => panic!("Invalid memory access: {:#x}", addr),
         }
     }
 
-    pub fn write(&mut self, addr: u16, x: u8) {
-        match addr {
-            0x0000 ... 0x1fff => {
-                let offset = self.mapper.map_chr(addr);
-                self.chr[offset] = x;
+    /// Write to the CPU's 0x4020-0xffff. The mapper sees every
+    /// write, and those to PRG RAM are stored if it is enabled.
+    /// Where the mapper has bus conflicts it sees the written
+    /// value ANDed with the PRG ROM at the same address.
+    pub fn cpu_write(&mut self, addr: u16, x: u8) {
+        let x = match addr {
+            0x8000 ..= 0xffff if self.mapper.bus_conflicts() => {
+                x & self.cpu_read(addr).unwrap_or(0xff)
             },
-            0x6000 ... 0x7fff => {
-                let offset = self.mapper.map_sram(addr);
-                self.sram[offset] = x;
-            },
-            0x8000 ... 0xffff => {
-                let offset = self.mapper.map_prg(addr);
-                self.prg[offset] = x;
-            },
-            _ => panic!("Invalid memory access: {:#x}", addr),
+            _ => x,
+        };
+        self.mapper.cpu_write(addr, x);
+        if let 0x6000 ..= 0x7fff = addr {
+            if let Some(offset) = self.mapper.map_sram(addr) {
+                let len = self.sram.len();
+                self.sram[offset % len] = x;
+            }
+        }
+    }
+
+    /// Read from the pattern tables at the PPU's 0x0000-0x1fff.
+    pub fn ppu_read(&self, addr: u16) -> u8 {
+        let offset = self.mapper.map_chr(addr);
+        self.chr[offset % self.chr.len()]
+    }
+
+    /// Write to the pattern tables, which only
+    /// takes if the cartridge has CHR RAM.
+    pub fn ppu_write(&mut self, addr: u16, x: u8) {
+        if self.chr_ram {
+            let offset = self.mapper.map_chr(addr);
+            let len = self.chr.len();
+            self.chr[offset % len] = x;
         }
     }
+
+    /// Tell the mapper about an address the PPU put on its bus.
+    pub fn ppu_address(&mut self, addr: u16) {
+        self.mapper.ppu_address(addr);
+    }
+
+    /// Level of the cartridge's IRQ output.
+    pub fn irq(&self) -> bool {
+        self.mapper.irq()
+    }
+
+    /// Clock the mapper for one CPU cycle.
+    pub fn tick(&mut self) {
+        self.mapper.tick();
+    }
+}
+
+#[cfg(test)]
+impl Cartridge {
+    /// An NROM cartridge with a blank 16K of PRG ROM and 8K of CHR RAM.
+    pub fn blank(mirroring: Mirroring) -> Cartridge {
+        let flags = match mirroring {
+            Mirroring::Vertical => 0b0001,
+            Mirroring::FourScreen => 0b1000,
+            _ => 0b0000,
+        };
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 1, 0, flags, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.resize(16 + 16384, 0);
+        Cartridge::new(data).unwrap()
+    }
+
+    /// An NROM cartridge with the given 16K of PRG ROM and 8K of CHR RAM.
+    pub fn with_prg(prg: &[u8]) -> Cartridge {
+        assert_eq!(prg.len(), 16384);
+        let mut data = vec![b'N', b'E', b'S', 0x1a, 1, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0];
+        data.extend_from_slice(prg);
+        Cartridge::new(data).unwrap()
+    }
 }
 
 #[derive(Debug)]
 pub enum Error {
     Io(io::Error),
     Parse(&'static str),
+    /// The ROM is for a mapper which isn't implemented.
+    UnsupportedMapper(u8),
 }
 
 impl fmt::Display for Error {
@@ -184,6 +270,7 @@ impl fmt::Display for Error {
         match *self {
             Error::Io(ref err) => write!(f, "IO error: {}", err),
             Error::Parse(err) => write!(f, "Parse error: {}", err),
+            Error::UnsupportedMapper(n) => write!(f, "Unsupported mapper: {}", n),
         }
     }
 }
@@ -191,15 +278,16 @@ impl fmt::Display for Error {
 impl error::Error for Error {
     fn description(&self) -> &str {
         match *self {
-            Error::Io(ref err) => err.description(),
+            Error::Io(_) => "IO error",
             Error::Parse(err) => err,
+            Error::UnsupportedMapper(_) => "Unsupported mapper",
         }
     }
 
-    fn cause(&self) -> Option<&error::Error> {
+    fn cause(&self) -> Option<&dyn error::Error> {
         match *self {
             Error::Io(ref err) => Some(err),
-            _ => Some(self)
+            _ => None,
         }
     }
 }